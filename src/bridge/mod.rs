@@ -3,15 +3,157 @@ pub trait LLMProvider {
     fn send_message(&self, message: &str) -> Result<String, Box<dyn std::error::Error>>;
 }
 
-use serde::{Deserialize, Serialize};
-use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize};
+use anyhow::{bail, Context, Result};
+use base64::Engine;
 use futures::Stream;
 use futures::StreamExt;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(into = "WireMessage")]
 pub struct Message {
     pub role: String,
+    /// 只有工具调用的 assistant 消息中 content 为 null
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub content: String,
+    /// 附带的图片（`data:` URL），有图片时 content 以多段数组的形式发送
+    #[serde(skip)]
+    pub images: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// `role: "tool"` 的消息所回应的工具调用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// 请求中消息的实际格式
+#[derive(Serialize)]
+struct WireMessage {
+    role: String,
+    content: Content,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+impl From<Message> for WireMessage {
+    fn from(message: Message) -> Self {
+        let content = if message.images.is_empty() {
+            Content::Text(message.content)
+        } else {
+            let text = ContentPart::Text { text: message.content };
+            let images = message.images.into_iter().map(|url| ContentPart::ImageUrl { image_url: ImageUrl { url } });
+            Content::Parts([text].into_iter().chain(images).collect())
+        };
+        Self {
+            role: message.role,
+            content,
+            tool_calls: message.tool_calls,
+            tool_call_id: message.tool_call_id,
+        }
+    }
+}
+
+impl Message {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.into(),
+            images: Vec::new(),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    /// 工具调用的结果
+    pub fn tool(tool_call_id: &str, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.to_string()),
+            ..Self::new("tool", content)
+        }
+    }
+}
+
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// assistant 消息中的一次函数调用
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON 编码的参数
+    pub arguments: String,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+/// 请求中声明的一个可调用函数
+#[derive(Debug, Serialize, Clone)]
+pub struct ToolDefinition {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    /// 参数的 JSON Schema
+    pub parameters: serde_json::Value,
+}
+
+impl ToolDefinition {
+    pub fn function(name: &str, description: &str, parameters: serde_json::Value) -> Self {
+        Self {
+            kind: function_type(),
+            function: FunctionDefinition {
+                name: name.to_string(),
+                description: description.to_string(),
+                parameters,
+            },
+        }
+    }
+}
+
+/// 流式响应中工具调用的一个片段，同一 `index` 的片段拼接后才是完整的调用
+#[derive(Debug, Clone, Default)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub arguments: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -31,16 +173,184 @@ pub struct ChatRequest {
     pub frequency_penalty: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// 要求模型以 JSON 回答：`json_object` 只保证是 JSON 对象，`json_schema` 还要符合给定的 schema
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub schema: serde_json::Value,
+    pub strict: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StreamOptions {
+    /// 让服务商在流的最后一个 chunk 中附带 `usage`
+    pub include_usage: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Transcription {
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpeechRequest {
+    pub model: String,
+    pub input: String,
+    pub voice: String,
+    /// 音频格式，如 `mp3`、`wav`、`opus`
+    pub response_format: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<Embedding>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Embedding {
+    embedding: Vec<f32>,
+    #[serde(default)]
+    index: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct RerankResponse {
+    results: Vec<RerankResult>,
+}
+
+/// 一个文档的重排序结果，`index` 是它在请求中的位置
+#[derive(Debug, Deserialize)]
+pub struct RerankResult {
+    pub index: usize,
+    pub relevance_score: f32,
+}
+
+/// `/images/generations` 的请求，同时带上 OpenAI 和 SiliconFlow 的参数名
+#[derive(Debug, Serialize)]
+pub struct ImageRequest {
+    pub model: String,
+    pub prompt: String,
+    /// 如 `1024x1024`
+    pub size: String,
+    pub image_size: String,
+    pub n: u32,
+    pub batch_size: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_inference_steps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negative_prompt: Option<String>,
+}
+
+/// OpenAI 返回 `data`，SiliconFlow 返回 `images`
+#[derive(Debug, Deserialize)]
+struct ImageResponse {
+    #[serde(default, alias = "images")]
+    data: Vec<GeneratedImage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeneratedImage {
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    b64_json: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ChatResponse {
     pub choices: Vec<Choice>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Choice {
     pub message: Message,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub completion_tokens: u32,
+    #[serde(default)]
+    pub total_tokens: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CompletionTokensDetails {
+    /// 思考过程消耗的 token，已包含在 `completion_tokens` 中
+    #[serde(default)]
+    pub reasoning_tokens: u32,
+}
+
+impl Usage {
+    /// 累加多次请求（如工具调用的每一轮）的用量
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        let reasoning = self.reasoning_tokens() + other.reasoning_tokens();
+        if reasoning > 0 {
+            self.completion_tokens_details = Some(CompletionTokensDetails { reasoning_tokens: reasoning });
+        }
+    }
+
+    pub fn reasoning_tokens(&self) -> u32 {
+        self.completion_tokens_details
+            .as_ref()
+            .map_or(0, |details| details.reasoning_tokens)
+    }
+}
+
+/// `/models` 接口返回的一个模型
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModelInfo {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owned_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<ModelInfo>,
+}
+
+/// 流式响应中的一个增量，各字段只在对应的 chunk 中出现
+#[derive(Debug, Clone, Default)]
+pub struct StreamDelta {
+    pub content: Option<String>,
+    pub reasoning: Option<String>,
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+    pub model: Option<String>,
+    pub tool_calls: Vec<ToolCallDelta>,
 }
 
 pub struct Bridge {
@@ -54,6 +364,8 @@ pub struct Bridge {
     top_p: Option<f32>,
     top_k: Option<u32>,
     frequency_penalty: Option<f32>,
+    stream_usage: bool,
+    response_format: Option<ResponseFormat>,
 }
 
 impl Bridge {
@@ -68,9 +380,15 @@ impl Bridge {
             top_p: None,
             top_k: None,
             frequency_penalty: None,
+            stream_usage: true,
+            response_format: None,
         }
     }
 
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
     // 配置方法
     pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
         self.max_tokens = Some(max_tokens);
@@ -101,10 +419,7 @@ impl Bridge {
     pub async fn chat(&self, message: &str) -> Result<String> {
         let request = ChatRequest {
             model: self.model.clone(),
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: message.to_string(),
-            }],
+            messages: vec![Message::new("user", message)],
             stream: false,
             max_tokens: self.max_tokens,
             temperature: self.temperature,
@@ -112,6 +427,9 @@ impl Bridge {
             top_k: self.top_k,
             frequency_penalty: self.frequency_penalty,
             n: Some(1),
+            stream_options: None,
+            tools: Vec::new(),
+            response_format: None,
         };
 
         let response = self.client
@@ -121,22 +439,16 @@ impl Bridge {
             .json(&request)
             .send()
             .await?;
-        
-        // debug
-        println!("Raw Response: {:?}", response);
 
         let chat_response: ChatResponse = response.json().await?;
         Ok(chat_response.choices[0].message.content.clone())
     }
 
     // 流式聊天方法
-    pub async fn chat_stream(&self, message: &str) -> Result<impl Stream<Item = Result<String>>> {
+    pub async fn chat_stream(&self, message: &str) -> Result<impl Stream<Item = Result<StreamDelta>>> {
         let request = ChatRequest {
             model: self.model.clone(),
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: message.to_string(),
-            }],
+            messages: vec![Message::new("user", message)],
             stream: true,
             max_tokens: self.max_tokens,
             temperature: self.temperature,
@@ -144,6 +456,9 @@ impl Bridge {
             top_k: self.top_k,
             frequency_penalty: self.frequency_penalty,
             n: Some(1),
+            stream_options: self.stream_options(),
+            tools: Vec::new(),
+            response_format: self.response_format.clone(),
         };
 
         let response = self.client
@@ -154,20 +469,23 @@ impl Bridge {
             .send()
             .await?;
 
-        let stream = response
-            .bytes_stream()
-            .map(|result| {
-                result.map_err(anyhow::Error::from)
-                    .and_then(|bytes| {
-                        let text = String::from_utf8(bytes.to_vec())?;
-                        match Self::process_stream_data(&text)? {
-                            Some(content) => Ok(content),
-                            None => Ok("".to_string())
-                        }
-                    })
-            });
+        Ok(Self::delta_stream(Self::check_status(response).await?))
+    }
+
+    /// 流式请求是否带上 `stream_options.include_usage`，不支持该参数的服务商需要关闭
+    pub fn with_stream_usage(mut self, stream_usage: bool) -> Self {
+        self.stream_usage = stream_usage;
+        self
+    }
 
-        Ok(stream)
+    /// 对话请求带上的 `response_format`
+    pub fn with_response_format(mut self, response_format: Option<ResponseFormat>) -> Self {
+        self.response_format = response_format;
+        self
+    }
+
+    fn stream_options(&self) -> Option<StreamOptions> {
+        self.stream_usage.then_some(StreamOptions { include_usage: true })
     }
 
     // 新增：支持带历史记录的对话方法
@@ -182,6 +500,9 @@ impl Bridge {
             top_k: self.top_k,
             frequency_penalty: self.frequency_penalty,
             n: Some(1),
+            stream_options: None,
+            tools: Vec::new(),
+            response_format: self.response_format.clone(),
         };
 
         let response = self.client
@@ -196,36 +517,275 @@ impl Bridge {
         Ok(chat_response.choices[0].message.content.clone())
     }
 
-    fn process_stream_data(text: &str) -> Result<Option<String>> {
-        let chunks = text.split("\n").collect::<Vec<_>>();
-        let mut result = String::new();
+    // 代理转发
+    /// 原样转发一个兼容 OpenAI 的聊天请求，未指定模型时使用配置的模型
+    pub async fn forward_chat(&self, mut body: serde_json::Value) -> Result<reqwest::Response> {
+        if let Some(map) = body.as_object_mut() {
+            map.entry("model")
+                .or_insert_with(|| serde_json::Value::String(self.model.clone()));
+        }
 
-        for chunk in chunks.iter() {
-            if chunk.is_empty() {
-                continue;
-            }
+        let response = self.client
+            .post(&self.api_base)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Content-Type", "application/json")
+            .json(&body)
+            .send()
+            .await?;
+        Ok(response)
+    }
 
-            let chunk = chunk.trim_start_matches("data:").trim();
+    /// 以 GET 方式请求与 `api_base` 同级的接口，例如 `models`
+    pub async fn forward_get(&self, path: &str) -> Result<reqwest::Response> {
+        let response = self.client
+            .get(self.endpoint(path))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+        Ok(response)
+    }
 
-            if let Ok(json_obj) = serde_json::from_str::<serde_json::Value>(chunk) {
-                if let Some(content) = json_obj["choices"][0]["delta"]["content"].as_str() {
-                    result.push_str(content);
-                } else if let Some(content) = json_obj["choices"][0]["delta"]["reasoning_content"].as_str() {
-                    result.push_str(content);
-                } else {
-                    return Err(anyhow::anyhow!("Json content error: {}", json_obj));
+    /// 查询服务商提供的模型列表
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let response = Self::check_status(self.forward_get("models").await?).await?;
+        let list: ModelList = response
+            .json()
+            .await
+            .context("Failed to parse model list")?;
+        Ok(list.data)
+    }
+
+    /// 调用 `/audio/transcriptions` 把音频转写为文字
+    pub async fn transcribe(&self, model: &str, file_name: &str, audio: Vec<u8>) -> Result<String> {
+        let form = reqwest::multipart::Form::new()
+            .text("model", model.to_string())
+            .part("file", reqwest::multipart::Part::bytes(audio).file_name(file_name.to_string()));
+        let response = self.client
+            .post(self.endpoint("audio/transcriptions"))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .multipart(form)
+            .send()
+            .await?;
+        let transcription: Transcription = Self::check_status(response)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse transcription")?;
+        Ok(transcription.text)
+    }
+
+    /// 调用 `/audio/speech` 把文字合成为语音，返回 `response_format` 格式的音频数据
+    pub async fn speech(&self, request: &SpeechRequest) -> Result<Vec<u8>> {
+        let response = self.client
+            .post(self.endpoint("audio/speech"))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .json(request)
+            .send()
+            .await?;
+        let audio = Self::check_status(response)
+            .await?
+            .bytes()
+            .await
+            .context("Failed to download synthesized speech")?;
+        Ok(audio.to_vec())
+    }
+
+    /// 调用 `/embeddings`，按输入的顺序返回向量，以及服务商报告的用量
+    pub async fn embed(&self, model: &str, inputs: &[String]) -> Result<(Vec<Vec<f32>>, Option<Usage>)> {
+        let response = self.client
+            .post(self.endpoint("embeddings"))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .json(&serde_json::json!({ "model": model, "input": inputs, "encoding_format": "float" }))
+            .send()
+            .await?;
+        let mut embeddings: EmbeddingResponse = Self::check_status(response)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse embeddings")?;
+        if embeddings.data.len() != inputs.len() {
+            bail!("Expected {} embeddings, got {}", inputs.len(), embeddings.data.len());
+        }
+        embeddings.data.sort_by_key(|embedding| embedding.index);
+        let vectors = embeddings.data.into_iter().map(|embedding| embedding.embedding).collect();
+        Ok((vectors, embeddings.usage))
+    }
+
+    /// 调用 `/rerank` 按和 `query` 的相关程度给文档排序，返回最相关的 `top_n` 个
+    pub async fn rerank(&self, model: &str, query: &str, documents: &[String], top_n: usize) -> Result<Vec<RerankResult>> {
+        let response = self.client
+            .post(self.endpoint("rerank"))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .json(&serde_json::json!({
+                "model": model,
+                "query": query,
+                "documents": documents,
+                "top_n": top_n,
+                "return_documents": false,
+            }))
+            .send()
+            .await?;
+        let mut reranked: RerankResponse = Self::check_status(response)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse rerank results")?;
+        if let Some(result) = reranked.results.iter().find(|result| result.index >= documents.len()) {
+            bail!("Rerank result refers to document {} of {}", result.index, documents.len());
+        }
+        reranked.results.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+        reranked.results.truncate(top_n);
+        Ok(reranked.results)
+    }
+
+    /// 调用 `/images/generations` 生成图片，返回每张图片的数据（服务商返回链接时会下载下来）
+    pub async fn generate_images(&self, request: &ImageRequest) -> Result<Vec<Vec<u8>>> {
+        let response = self.client
+            .post(self.endpoint("images/generations"))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .json(request)
+            .send()
+            .await?;
+        let generated: ImageResponse = Self::check_status(response)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse image generation response")?;
+
+        let mut images = Vec::new();
+        for image in generated.data {
+            let bytes = match (image.b64_json, image.url) {
+                (Some(data), _) => base64::engine::general_purpose::STANDARD
+                    .decode(data)
+                    .context("Invalid base64 image data")?,
+                (None, Some(url)) => {
+                    let response = Self::check_status(self.client.get(&url).send().await?).await?;
+                    response.bytes().await.context("Failed to download generated image")?.to_vec()
                 }
-            } else if chunk.contains("DONE") {
-                return Ok(None);
-            } else {
-                return Err(anyhow::anyhow!("Failed to parse json: {}", chunk));
-            }
+                (None, None) => bail!("The provider returned an image without data"),
+            };
+            images.push(bytes);
+        }
+        Ok(images)
+    }
+
+    /// 由 `api_base`（`.../v1/chat/completions`）推导出同一 API 版本下的其他接口地址
+    pub fn endpoint(&self, path: &str) -> String {
+        let base = self.api_base.trim_end_matches('/');
+        let base = base.strip_suffix("/chat/completions").unwrap_or(base);
+        format!("{}/{}", base, path.trim_start_matches('/'))
+    }
+
+    async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        bail!("Request failed with status {}: {}", status, body.trim())
+    }
+
+    fn delta_stream(response: reqwest::Response) -> impl Stream<Item = Result<StreamDelta>> {
+        Self::sse_deltas(response.bytes_stream())
+    }
+
+    /// 将 SSE 字节流按行切分并解析为增量，一个网络 chunk 中可能包含多行，也可能只有半行
+    ///
+    /// 流结束时缓冲区里没有换行的最后一行也要解析：有的服务商就这样发送只含 usage 的 chunk。
+    fn sse_deltas<S, B, E>(chunks: S) -> impl Stream<Item = Result<StreamDelta>>
+    where
+        S: Stream<Item = std::result::Result<B, E>>,
+        B: AsRef<[u8]>,
+        E: Into<anyhow::Error>,
+    {
+        chunks
+            .map(Some)
+            .chain(futures::stream::once(futures::future::ready(None)))
+            .scan(Vec::new(), |buffer: &mut Vec<u8>, chunk| {
+                let mut lines = Vec::new();
+                let mut deltas = Vec::new();
+                match chunk {
+                    Some(Ok(bytes)) => {
+                        buffer.extend_from_slice(bytes.as_ref());
+                        while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                            lines.push(buffer.drain(..=pos).collect::<Vec<u8>>());
+                        }
+                    }
+                    Some(Err(e)) => deltas.push(Err(e.into())),
+                    None => lines.push(std::mem::take(buffer)),
+                }
+                for line in lines {
+                    let delta = String::from_utf8(line)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|line| Self::process_stream_line(&line));
+                    if let Some(delta) = delta.transpose() {
+                        deltas.push(delta);
+                    }
+                }
+                futures::future::ready(Some(futures::stream::iter(deltas)))
+            })
+            .flatten()
+    }
+
+    fn process_stream_line(line: &str) -> Result<Option<StreamDelta>> {
+        // 空行、注释（": keep-alive"）和 event: 行都不携带数据
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            return Ok(None);
+        };
+        let data = data.trim();
+        if data.is_empty() || data == "[DONE]" {
+            return Ok(None);
+        }
+
+        let json_obj: serde_json::Value = serde_json::from_str(data)
+            .with_context(|| format!("Failed to parse json: {}", data))?;
+        if let Some(error) = json_obj.get("error") {
+            bail!("API error: {}", error);
         }
 
-        Ok(Some(result))
+        let choice = &json_obj["choices"][0];
+        let text = |value: &serde_json::Value| {
+            value.as_str().filter(|s| !s.is_empty()).map(str::to_string)
+        };
+        // 有的服务商在每个 chunk 都带 usage（可能部分字段为 null），解析不了的直接忽略
+        let usage = json_obj
+            .get("usage")
+            .filter(|usage| !usage.is_null())
+            .and_then(|usage| serde_json::from_value(usage.clone()).ok());
+
+        let tool_calls = choice["delta"]["tool_calls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(i, call)| ToolCallDelta {
+                index: call["index"].as_u64().map_or(i, |index| index as usize),
+                id: text(&call["id"]),
+                name: text(&call["function"]["name"]),
+                arguments: text(&call["function"]["arguments"]),
+            })
+            .collect();
+
+        Ok(Some(StreamDelta {
+            content: text(&choice["delta"]["content"]),
+            reasoning: text(&choice["delta"]["reasoning_content"]),
+            finish_reason: text(&choice["finish_reason"]),
+            usage,
+            model: text(&json_obj["model"]),
+            tool_calls,
+        }))
     }
 
-    pub async fn chat_with_history_stream(&self, messages: &[Message]) -> Result<impl Stream<Item = Result<String>>> {
+    pub async fn chat_with_history_stream(&self, messages: &[Message]) -> Result<impl Stream<Item = Result<StreamDelta>>> {
+        self.chat_with_tools_stream(messages, &[]).await
+    }
+
+    /// 同 `chat_with_history_stream`，并声明可供模型调用的函数
+    pub async fn chat_with_tools_stream(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+    ) -> Result<impl Stream<Item = Result<StreamDelta>>> {
         let request = ChatRequest {
             model: self.model.clone(),
             messages: messages.to_vec(),
@@ -236,6 +796,9 @@ impl Bridge {
             top_k: self.top_k,
             frequency_penalty: self.frequency_penalty,
             n: Some(1),
+            stream_options: self.stream_options(),
+            tools: tools.to_vec(),
+            response_format: self.response_format.clone(),
         };
 
         let response = self.client
@@ -246,19 +809,74 @@ impl Bridge {
             .send()
             .await?;
 
-        let stream = response
-            .bytes_stream()
-            .map(|result| {
-                result.map_err(anyhow::Error::from)
-                    .and_then(|bytes| {
-                        let text = String::from_utf8(bytes.to_vec())?;
-                        match Self::process_stream_data(&text)? {
-                            Some(content) => Ok(content),
-                            None => Ok("".to_string())
-                        }
-                    })
-            });
+        Ok(Self::delta_stream(Self::check_status(response).await?))
+    }
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(stream)
+    fn delta(line: &str) -> Option<StreamDelta> {
+        Bridge::process_stream_line(line).unwrap()
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn parses_content_and_tool_call_deltas() {
+        let content = delta(r#"data: {"model":"m","choices":[{"delta":{"content":"Hi","reasoning_content":""}}]}"#).unwrap();
+        assert_eq!(content.content.as_deref(), Some("Hi"));
+        assert_eq!(content.reasoning, None);
+        assert_eq!(content.model.as_deref(), Some("m"));
+
+        let call = delta(
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":1,"id":"c1","function":{"name":"ls","arguments":"{}"}}]},"finish_reason":"tool_calls"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(call.tool_calls[0].index, 1);
+        assert_eq!(call.tool_calls[0].name.as_deref(), Some("ls"));
+        assert_eq!(call.finish_reason.as_deref(), Some("tool_calls"));
+    }
+
+    #[test]
+    fn parses_usage_only_chunks() {
+        let usage = delta(r#"data: {"choices":[],"usage":{"prompt_tokens":3,"completion_tokens":5,"total_tokens":8}}"#)
+            .unwrap()
+            .usage
+            .unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (3, 5, 8));
+        // 部分字段为 null 的 usage 直接忽略
+        assert!(delta(r#"data: {"choices":[],"usage":{"prompt_tokens":null}}"#).unwrap().usage.is_none());
+        assert!(delta(r#"data: {"choices":[],"usage":null}"#).unwrap().usage.is_none());
+    }
+
+    #[test]
+    fn skips_lines_without_data() {
+        for line in ["data: [DONE]", "data:[DONE]\n", ": keep-alive", "event: message", "", "data: "] {
+            assert!(delta(line).is_none(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn reports_error_payloads() {
+        let error = Bridge::process_stream_line(r#"data: {"error":{"message":"rate limited"}}"#).unwrap_err();
+        assert!(error.to_string().contains("rate limited"), "{}", error);
+        assert!(Bridge::process_stream_line("data: {not json").is_err());
+    }
+
+    #[tokio::test]
+    async fn splits_chunks_and_flushes_the_last_line() {
+        let chunks: Vec<std::result::Result<&[u8], anyhow::Error>> = vec![
+            Ok(b"data: {\"choices\":[{\"delta\":{\"content\":\"He\"}}]}\n\ndata: {\"choi"),
+            Ok(b"ces\":[{\"delta\":{\"content\":\"llo\"}}]}\n: keep-alive\n\n"),
+            // 最后一个 chunk 没有换行
+            Ok(b"data: {\"choices\":[],\"usage\":{\"prompt_tokens\":1,\"completion_tokens\":2,\"total_tokens\":3}}"),
+        ];
+        let deltas: Vec<StreamDelta> = Bridge::sse_deltas(futures::stream::iter(chunks))
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let content: String = deltas.iter().filter_map(|delta| delta.content.as_deref()).collect();
+        assert_eq!(content, "Hello");
+        assert_eq!(deltas.last().unwrap().usage.as_ref().unwrap().total_tokens, 3);
+    }
+}
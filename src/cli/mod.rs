@@ -1,6 +1,27 @@
-use crate::core::Core;
-use anyhow::{Result, Context};
-use clap::{Parser, Subcommand};
+use crate::code::{self, Interpreter};
+use crate::config::{secret, BuiltinTool, Config};
+use crate::bridge::{ImageRequest, Usage};
+use crate::core::{ChatEvent, Core, Turn, TurnInput};
+use crate::index::{self, history, Hit, Index};
+use crate::mcp;
+use crate::models;
+use crate::usage::{self, GroupBy};
+use crate::render::{Output, RenderMode, Renderer};
+use crate::server::{self, ServerEvent};
+use crate::structured::StructuredOutput;
+use anyhow::{bail, Result, Context};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
+use clap_complete::CompleteEnv;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `--recall` 加入的历史对话轮数
+const RECALL_TURNS: usize = 3;
 
 #[derive(Parser)]
 #[command(name = "chat")]
@@ -9,6 +30,82 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
+    /// Path to the config file (overrides CHAT_CONFIG and the default location)
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Model or model alias to use for this call only
+    #[arg(short, long, global = true, add = ArgValueCompleter::new(complete_model))]
+    model: Option<String>,
+
+    /// Use a `[profiles.<name>]` section from the config for this call
+    #[arg(long, global = true, add = ArgValueCompleter::new(complete_profile))]
+    profile: Option<String>,
+
+    /// How to display the answer (defaults to markdown on a terminal, raw otherwise)
+    #[arg(long, value_enum, global = true)]
+    render: Option<RenderMode>,
+
+    /// Print machine-readable JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Print one JSON event per line while the answer streams
+    #[arg(long, global = true, conflicts_with = "json")]
+    jsonl: bool,
+
+    /// Print token usage after the answer
+    #[arg(long)]
+    stats: bool,
+
+    /// Built-in tools to enable for this call, replacing `builtin_tools` (e.g. read_file,list_dir)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    tools: Option<Vec<BuiltinTool>>,
+
+    /// Start the configured MCP servers and let the model call their tools
+    #[arg(long)]
+    mcp: bool,
+
+    /// Attach an image to the message (for vision models), may be repeated
+    #[arg(short, long = "image", value_name = "FILE")]
+    images: Vec<PathBuf>,
+
+    /// Transcribe this audio file and send the text as the message
+    #[arg(long, value_name = "FILE")]
+    voice_in: Option<PathBuf>,
+
+    /// Also save the answer as speech to this audio file (mp3, wav, opus or pcm)
+    #[arg(long, value_name = "FILE")]
+    speak: Option<PathBuf>,
+
+    /// Require the answer to be a JSON object
+    #[arg(long, conflicts_with = "json_schema")]
+    json_object: bool,
+
+    /// Require the answer to be JSON matching the JSON Schema in this file
+    #[arg(long, value_name = "FILE")]
+    json_schema: Option<PathBuf>,
+
+    /// Ask the provider to follow --json-schema strictly (OpenAI needs `additionalProperties: false` and every property required)
+    #[arg(long, requires = "json_schema")]
+    strict: bool,
+
+    /// Ask the model to fix an answer that fails --json-object/--json-schema validation, up to N times
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    json_retries: u32,
+
+    /// Add the most relevant past conversations (from any session) to the message as context
+    #[arg(long)]
+    recall: bool,
+
+    /// Start the message with a prompt from an MCP server (see `chat prompts list`)
+    #[arg(long, value_name = "NAME")]
+    prompt: Option<String>,
+
+    /// Argument for --prompt, may be repeated
+    #[arg(long = "arg", value_name = "KEY=VALUE", value_parser = parse_key_value, requires = "prompt")]
+    prompt_args: Vec<(String, String)>,
+
     /// Message to send (when no subcommand is provided)
     #[arg(trailing_var_arg = true)]
     message: Vec<String>,
@@ -26,6 +123,108 @@ enum Commands {
         #[command(subcommand)]
         action: HistoryAction,
     },
+    /// Work with code blocks from the last answer
+    Code {
+        #[command(subcommand)]
+        action: CodeAction,
+    },
+    /// Models offered by the provider
+    Models {
+        #[command(subcommand)]
+        action: ModelsAction,
+    },
+    /// Tools, resources and prompts of the configured MCP servers
+    Mcp {
+        #[command(subcommand)]
+        action: McpAction,
+    },
+    /// Prompts provided by MCP servers
+    Prompts {
+        #[command(subcommand)]
+        action: PromptsAction,
+    },
+    /// Transcribe an audio file to text
+    Transcribe {
+        file: PathBuf,
+    },
+    /// Turn text into speech and save it as an audio file
+    Tts {
+        /// Text to speak (read from stdin when omitted)
+        text: Option<String>,
+        /// Output file, its extension picks the format (mp3, wav, opus or pcm)
+        #[arg(short, long, default_value = "speech.mp3")]
+        output: PathBuf,
+        /// Voice to use instead of `voice` from the config
+        #[arg(long)]
+        voice: Option<String>,
+    },
+    /// Generate images from a prompt and save them
+    Image {
+        prompt: String,
+        /// Image size as WIDTHxHEIGHT
+        #[arg(long, default_value = "1024x1024", value_parser = parse_size)]
+        size: String,
+        /// Number of inference steps
+        #[arg(long)]
+        steps: Option<u32>,
+        /// Random seed, for reproducible images
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Number of images to generate
+        #[arg(short = 'n', long, default_value_t = 1)]
+        count: u32,
+        /// What the images should not contain
+        #[arg(long)]
+        negative: Option<String>,
+        /// Directory to save the images in
+        #[arg(short, long, default_value = ".")]
+        output_dir: PathBuf,
+    },
+    /// Print the embedding vector of a text or a text file
+    Embed {
+        /// Text to embed, or the path of a file to read it from
+        input: String,
+    },
+    /// Local semantic indexes over documents
+    Index {
+        #[command(subcommand)]
+        action: IndexAction,
+    },
+    /// Answer a question using the most relevant excerpts of an index as context
+    Ask {
+        /// Index to search (see `chat index list`)
+        #[arg(long, value_name = "INDEX")]
+        docs: String,
+        /// Number of excerpts to include
+        #[arg(short = 'k', long, default_value_t = 5)]
+        top_k: usize,
+        /// Print the retrieved excerpts and their scores to stderr
+        #[arg(long)]
+        verbose: bool,
+        #[arg(required = true)]
+        question: Vec<String>,
+    },
+    /// Token usage and cost report
+    Usage {
+        /// How to group the report
+        #[arg(long, value_enum, default_value = "day")]
+        by: GroupBy,
+        /// Only include requests on or after this date (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE")]
+        since: Option<chrono::NaiveDate>,
+    },
+    /// Serve an OpenAI-compatible API that forwards to the configured provider
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// Address to bind
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Allow binding a non-loopback address (the proxy has no authentication)
+        #[arg(long)]
+        allow_remote: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -35,13 +234,83 @@ enum ConfigAction {
         /// Configuration key (optional)
         #[arg(default_value = None)]
         key: Option<String>,
+        /// Show which layer (default, global, project, env) each value comes from
+        #[arg(long)]
+        show_origin: bool,
+        /// Print the token in full instead of masking it
+        #[arg(long)]
+        reveal: bool,
     },
     /// Set configuration value
     Set {
-        /// Configuration key
+        /// Configuration key, nested keys are separated by dots
+        #[arg(add = ArgValueCompleter::new(complete_config_key))]
         key: String,
-        /// New value
+        /// New value (`none` clears an optional value)
+        #[arg(add = ArgValueCompleter::new(complete_config_value))]
         value: String,
+        /// Don't check `model` against the provider's model list
+        #[arg(long)]
+        force: bool,
+    },
+    /// Reset a configuration value to its default
+    Unset {
+        /// Configuration key, nested keys are separated by dots
+        key: String,
+    },
+    /// Open the config file in $EDITOR and validate it on save
+    Edit,
+    /// Store the token in the system keyring instead of the config file
+    Keyring {
+        /// Token to store (read from stdin when omitted, keeping it out of shell history)
+        token: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModelsAction {
+    /// List the models available from the configured provider
+    List {
+        /// Only show models whose ID contains this text
+        #[arg(long)]
+        filter: Option<String>,
+        /// Ignore the cached list and query the provider again
+        #[arg(long)]
+        refresh: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum McpAction {
+    /// Connect to each server and list what it offers
+    List,
+}
+
+#[derive(Subcommand)]
+enum IndexAction {
+    /// Chunk and embed the text files under a directory, updating only changed files
+    Add {
+        dir: PathBuf,
+        /// Index name, defaults to the directory name
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// List the indexes
+    List,
+    /// Delete an index
+    Remove { name: String },
+}
+
+#[derive(Subcommand)]
+enum PromptsAction {
+    /// List the available prompts and their arguments
+    List,
+    /// Print the messages a prompt expands to
+    Show {
+        name: String,
+        /// Prompt argument, may be repeated
+        #[arg(long = "arg", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        args: Vec<(String, String)>,
     },
 }
 
@@ -51,77 +320,96 @@ enum HistoryAction {
     Clear,
     /// Get chat history
     Get,
+    /// Find past conversations by meaning, including ones no longer kept in history
+    Similar {
+        query: String,
+        /// Number of conversations to show
+        #[arg(short = 'k', long, default_value_t = 5)]
+        top_k: usize,
+    },
 }
 
-pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+#[derive(Subcommand)]
+enum CodeAction {
+    /// List code blocks in the last answer
+    List,
+    /// Copy a code block to the clipboard
+    Copy {
+        /// Code block number (see `chat code list`)
+        n: usize,
+    },
+    /// Save a code block to a file
+    Save {
+        /// Code block number (see `chat code list`)
+        n: usize,
+        /// Destination file
+        path: PathBuf,
+    },
+    /// Run a shell or python code block after confirmation
+    Run {
+        /// Code block number (see `chat code list`)
+        n: usize,
+        /// Interpreter to use when the block has no language tag (sh or python)
+        #[arg(long)]
+        lang: Option<String>,
+    },
+}
+
+pub async fn run() -> Result<()> {
+    // 设置了 COMPLETE 环境变量时输出补全脚本或补全结果，然后直接退出
+    CompleteEnv::with_factory(Cli::command).complete();
+
     let cli = Cli::parse();
-    let mut core = Core::new()?;
+    let mut config = Config::load_with_profile(cli.config.as_deref(), cli.profile.as_deref())?;
+    if let Some(model) = &cli.model {
+        config.override_model(model);
+    }
+    if let Some(tools) = cli.tools.clone() {
+        config.override_builtin_tools(tools);
+    }
+    let mut core = Core::with_config(config);
+    let structured = match &cli.json_schema {
+        Some(path) => Some(StructuredOutput::from_schema_file(path, cli.strict)?),
+        None => cli.json_object.then_some(StructuredOutput::Object),
+    };
+    core.set_structured_output(structured, cli.json_retries);
+
+    print_warnings(&mut core);
+    let result = dispatch(&mut core, cli).await;
+    print_warnings(&mut core);
+    result
+}
 
+/// 执行子命令，没有子命令时发送消息
+async fn dispatch(core: &mut Core, cli: Cli) -> Result<()> {
     match cli.command {
         Some(Commands::Config { action }) => {
-            match action {
-                ConfigAction::Get { key } => {
-                    let config = core.get_config();
-                    match key {
-                        Some(key) => match key.as_str() {
-                            "api_base" => println!("api_base: {}", config.api_base),
-                            "token" => println!("token: {}", config.token),
-                            "model" => println!("model: {}", config.model),
-                            "max_tokens" => println!("max_tokens: {:?}", config.max_tokens),
-                            "temperature" => println!("temperature: {:?}", config.temperature),
-                            "max_history" => println!("max_history: {}", config.max_history),
-                            _ => println!("Unknown configuration key: {}", key),
-                        },
-                        None => {
-                            println!("Current configuration:");
-                            println!("  api_base: {}", config.api_base);
-                            println!("  token: {}", config.token);
-                            println!("  model: {}", config.model);
-                            println!("  max_tokens: {:?}", config.max_tokens);
-                            println!("  temperature: {:?}", config.temperature);
-                            println!("  max_history: {}", config.max_history);
-                        }
-                    }
-                }
-                ConfigAction::Set { key, value } => {
-                    let mut new_config = core.get_config().clone();
-                    match key.as_str() {
-                        "api_base" => new_config.api_base = value,
-                        "token" => new_config.token = value,
-                        "model" => new_config.model = value,
-                        "max_tokens" => {
-                            new_config.max_tokens = if value.to_lowercase() == "none" {
-                                None
-                            } else {
-                                Some(value.parse().context("Invalid max_tokens value")?)
-                            }
-                        }
-                        "temperature" => {
-                            new_config.temperature = if value.to_lowercase() == "none" {
-                                None
-                            } else {
-                                Some(value.parse().context("Invalid temperature value")?)
-                            }
-                        }
-                        "max_history" => {
-                            new_config.max_history = value.parse().context("Invalid max_history value")?;
-                        }
-                        _ => {
-                            println!("Unknown configuration key: {}", key);
-                            return Ok(());
-                        }
+            run_config(core, action, cli.json).await?;
+        }
+        Some(Commands::Models { action }) => match action {
+            ModelsAction::List { filter, refresh } => {
+                let list = core.models(refresh).await?;
+                let list = models::filter(&list, filter.as_deref().unwrap_or(""));
+                if cli.json {
+                    println!("{}", serde_json::to_string_pretty(&list)?);
+                } else if list.is_empty() {
+                    println!("No models found");
+                } else {
+                    for model in list {
+                        println!("{}", model.id);
                     }
-                    core.update_config(new_config)?;
-                    println!("Configuration updated successfully");
                 }
             }
-        }
+        },
         Some(Commands::History { action }) => {
             match action {
                 HistoryAction::Clear => {
                     core.clear_history()?;
                     println!("Chat history cleared");
                 }
+                HistoryAction::Get if cli.json => {
+                    println!("{}", serde_json::to_string_pretty(core.get_config().get_history())?);
+                }
                 HistoryAction::Get => {
                     let history = core.get_config().get_history();
                     if history.is_empty() {
@@ -130,23 +418,266 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
                         let mut is_end_with_nn = false;
                         for (i, item) in history.iter().enumerate() {
                             if !is_end_with_nn {
-                                println!("");
+                                println!();
+                            }
+                            let meta: Vec<String> = item
+                                .model
+                                .iter()
+                                .cloned()
+                                .chain(item.profile.iter().map(|profile| format!("profile {}", profile)))
+                                .chain((!item.images.is_empty()).then(|| format!("{} image(s)", item.images.len())))
+                                .chain(item.usage.iter().map(|usage| format!("{} tokens", usage.total_tokens)))
+                                .collect();
+                            if meta.is_empty() {
+                                println!("--- Conversation {} ---", i + 1);
+                            } else {
+                                println!("--- Conversation {} ({}) ---", i + 1, meta.join(", "));
                             }
-                            println!("--- Conversation {} ---", i + 1);
                             println!("Q: {}", item.question);
                             println!("A: {}", item.answer);
                             is_end_with_nn = item.answer.ends_with("\n\n");
                         }
                     }
                 }
+                HistoryAction::Similar { query, top_k } => {
+                    let matches = core.similar(&query, top_k).await?;
+                    if cli.json {
+                        println!("{}", serde_json::to_string_pretty(&matches)?);
+                    } else if matches.is_empty() {
+                        println!("No embedded conversations (set embed_history = true to embed turns as they are saved)");
+                    } else {
+                        for item in matches {
+                            let model = item.model.map(|model| format!(", {}", model)).unwrap_or_default();
+                            println!();
+                            println!("--- {} ({:.3}{}) ---", item.time.format("%Y-%m-%d %H:%M"), item.score, model);
+                            println!("Q: {}", item.question);
+                            println!("A: {}", summarize(&item.answer, 300));
+                        }
+                    }
+                }
+            }
+        }
+        Some(Commands::Code { action }) => {
+            let answer = core
+                .get_config()
+                .get_history()
+                .back()
+                .map(|item| item.answer.clone())
+                .unwrap_or_default();
+            let blocks = code::extract(&answer);
+
+            match action {
+                CodeAction::List => {
+                    if blocks.is_empty() {
+                        println!("No code blocks in the last answer");
+                    }
+                    for (i, block) in blocks.iter().enumerate() {
+                        let lang = if block.lang.is_empty() { "text" } else { &block.lang };
+                        println!("[{}] {} ({} lines): {}", i + 1, lang, block.code.lines().count(), block.summary());
+                    }
+                }
+                CodeAction::Copy { n } => {
+                    code::copy(code::nth(&blocks, n)?)?;
+                    println!("Code block {} copied to clipboard", n);
+                }
+                CodeAction::Save { n, path } => {
+                    code::save(code::nth(&blocks, n)?, &path)?;
+                    println!("Code block {} saved to {}", n, path.display());
+                }
+                CodeAction::Run { n, lang } => {
+                    let block = code::nth(&blocks, n)?;
+                    let lang = lang.as_deref().unwrap_or(&block.lang);
+                    let interpreter = Interpreter::from_lang(lang)
+                        .with_context(|| format!("Cannot run code block of language '{}' (supported: sh, python)", lang))?;
 
+                    println!("{}", block.code.trim_end());
+                    println!();
+                    if !code::confirm(&format!("Run this code block with {:?}?", interpreter))? {
+                        println!("Aborted");
+                        return Ok(());
+                    }
+                    let code = code::run(block, interpreter)?;
+                    if code != 0 {
+                        bail!("Code block exited with status {}", code);
+                    }
+                }
             }
         }
+        Some(Commands::Mcp { action: McpAction::List }) => {
+            mcp_list(core, cli.json)?;
+        }
+        Some(Commands::Prompts { action }) => match action {
+            PromptsAction::List => {
+                let prompts = core.prompts();
+                if prompts.list().next().is_none() {
+                    println!("No prompts available");
+                }
+                for prompt in prompts.list() {
+                    let arguments: Vec<String> = prompt
+                        .arguments
+                        .iter()
+                        .map(|argument| if argument.required { argument.name.clone() } else { format!("[{}]", argument.name) })
+                        .collect();
+                    println!("{} {}", prompt.name, arguments.join(" "));
+                    if let Some(description) = &prompt.description {
+                        println!("    {}", description);
+                    }
+                }
+            }
+            PromptsAction::Show { name, args } => {
+                let messages = core.prompts().render(&name, &args.into_iter().collect())?;
+                if cli.json {
+                    println!("{}", serde_json::to_string_pretty(&messages)?);
+                } else {
+                    for message in messages {
+                        println!("[{}]\n{}\n", message.role, message.content);
+                    }
+                }
+            }
+        },
+        Some(Commands::Transcribe { file }) => {
+            let text = core.transcribe(&file).await?;
+            if cli.json {
+                println!("{}", serde_json::json!({ "text": text }));
+            } else {
+                println!("{}", text.trim());
+            }
+        }
+        Some(Commands::Tts { text, output, voice }) => {
+            let format = audio_format(&output)?;
+            let text = match text {
+                Some(text) => text,
+                None => io::read_to_string(io::stdin()).context("Failed to read text from stdin")?,
+            };
+            if text.trim().is_empty() {
+                bail!("No text to speak");
+            }
+            let audio = core.speech(text.trim(), voice.as_deref(), format).await?;
+            fs::write(&output, audio).with_context(|| format!("Failed to write {}", output.display()))?;
+            println!("Speech saved to {}", output.display());
+        }
+        Some(Commands::Image { prompt, size, steps, seed, count, negative, output_dir }) => {
+            let request = ImageRequest {
+                model: core.get_config().image_model().to_string(),
+                prompt,
+                image_size: size.clone(),
+                size,
+                n: count,
+                batch_size: count,
+                num_inference_steps: steps,
+                seed,
+                negative_prompt: negative,
+            };
+            let paths = core.generate_images(&request, &output_dir).await?;
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&paths)?);
+            } else {
+                for path in paths {
+                    println!("{}", path.display());
+                }
+            }
+        }
+        Some(Commands::Embed { input }) => {
+            let path = Path::new(&input);
+            let text = if path.is_file() {
+                fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?
+            } else {
+                input
+            };
+            let model = core.get_config().embedding_model().to_string();
+            let vector = core.embed(&[text], &model).await?.pop().context("The provider returned no embedding")?;
+            if cli.json {
+                println!("{}", serde_json::json!({ "model": model, "embedding": vector }));
+            } else {
+                println!("{}", serde_json::to_string(&vector)?);
+            }
+        }
+        Some(Commands::Index { action }) => run_index(core, action, cli.json).await?,
+        Some(Commands::Ask { docs, top_k, verbose, question }) => {
+            let question = question.join(" ");
+            let hits = core.retrieve(&docs, &question, top_k).await?;
+            if hits.is_empty() {
+                bail!("Index `{}` is empty", docs);
+            }
+            if verbose {
+                for (i, hit) in hits.iter().enumerate() {
+                    let rerank = hit.rerank_score.map(|score| format!(", rerank {:.3}", score)).unwrap_or_default();
+                    eprintln!(
+                        "[{}] {}:{}-{} (similarity {:.3}{})",
+                        i + 1,
+                        hit.path.display(),
+                        hit.start_line,
+                        hit.end_line,
+                        hit.score,
+                        rerank
+                    );
+                }
+            }
+            let input = TurnInput {
+                message: question,
+                context: Some(index::context(&hits)),
+                ..TurnInput::default()
+            };
+            let output = chat_output(core, cli.json, cli.jsonl, cli.render);
+            let turn = chat(core, &input, output, &hits).await?;
+            if cli.stats {
+                eprintln!("{}", format_usage(turn.usage.as_ref()));
+            }
+        }
+        Some(Commands::Usage { by, since }) => {
+            usage_report(core.get_config(), by, since, cli.json)?;
+        }
+        Some(Commands::Serve { port, host, allow_remote }) => {
+            server::serve(core.get_config(), &host, port, allow_remote, Arc::new(print_server_event)).await?;
+        }
         None => {
-            if !cli.message.is_empty() {
-                let message = cli.message.join(" ");
-                println!("");
-                let _response = core.chat_stream(&message).await?;
+            if !cli.message.is_empty() || cli.prompt.is_some() || cli.voice_in.is_some() {
+                let speak_format = cli.speak.as_deref().map(audio_format).transpose()?;
+                let mut message = cli.message.join(" ");
+                if let Some(path) = &cli.voice_in {
+                    let transcript = core.transcribe(path).await?;
+                    eprintln!("🎤 {}", transcript.trim());
+                    message = [message, transcript.trim().to_string()]
+                        .into_iter()
+                        .filter(|part| !part.is_empty())
+                        .collect::<Vec<_>>()
+                        .join("\n\n");
+                }
+                if let Some(name) = &cli.prompt {
+                    let arguments = cli.prompt_args.iter().cloned().collect();
+                    let expanded: Vec<String> = core
+                        .prompts()
+                        .render(name, &arguments)?
+                        .into_iter()
+                        .map(|message| message.content)
+                        .chain((!message.is_empty()).then_some(message))
+                        .collect();
+                    message = expanded.join("\n\n");
+                }
+                let output = chat_output(core, cli.json, cli.jsonl, cli.render);
+                let context = if cli.recall {
+                    let matches = core.recall(&message, RECALL_TURNS).await?;
+                    (!matches.is_empty()).then(|| history::context(&matches))
+                } else {
+                    None
+                };
+                let input = TurnInput {
+                    message,
+                    images: cli.images.clone(),
+                    context,
+                };
+                if cli.mcp {
+                    core.connect_mcp();
+                }
+                let turn = chat(core, &input, output, &[]).await?;
+                if cli.stats {
+                    eprintln!("{}", format_usage(turn.usage.as_ref()));
+                }
+                if let (Some(path), Some(format)) = (&cli.speak, speak_format) {
+                    let audio = core.speech(&turn.answer, None, format).await?;
+                    fs::write(path, audio).with_context(|| format!("Failed to write {}", path.display()))?;
+                    eprintln!("Speech saved to {}", path.display());
+                }
             } else {
                 println!("No message provided. Use 'chat --help' for usage information.");
             }
@@ -154,4 +685,700 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// 把库中积攒的提示输出到 stderr
+fn print_warnings(core: &mut Core) {
+    for warning in core.take_warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+/// `chat serve` 的日志，写到 stderr
+fn print_server_event(event: &ServerEvent) {
+    match event {
+        ServerEvent::Listening { address } => {
+            eprintln!("Serving OpenAI-compatible API on http://{}", address);
+            eprintln!("  POST /v1/chat/completions");
+            eprintln!("  GET  /v1/models");
+        }
+        ServerEvent::Request { method, path, model, stream, status, usage, elapsed_ms } => {
+            let model = model.as_ref().map(|model| format!(" model={} stream={}", model, stream)).unwrap_or_default();
+            let tokens = usage
+                .as_ref()
+                .map(|usage| format!(" tokens={}/{}", usage.prompt_tokens, usage.completion_tokens))
+                .unwrap_or_default();
+            eprintln!("{} {}{} status={}{} {}ms", method, path, model, status, tokens, elapsed_ms);
+        }
+        ServerEvent::Warning { message } => eprintln!("Warning: {}", message),
+        ServerEvent::Error { message } => eprintln!("Error: {}", message),
+    }
+}
+
+/// 根据命令行参数决定回答的输出方式
+fn chat_output(core: &Core, json: bool, jsonl: bool, render: Option<RenderMode>) -> Output {
+    if json {
+        Output::Json
+    } else if jsonl {
+        Output::Jsonl
+    } else if core.structured_output().is_some() {
+        // JSON 回答校验通过后原样输出，便于管道处理
+        Output::Text(RenderMode::Raw)
+    } else {
+        println!();
+        Output::Text(RenderMode::resolve(render))
+    }
+}
+
+async fn run_index(core: &mut Core, action: IndexAction, json: bool) -> Result<()> {
+    let dir = core.get_config().index_dir();
+    match action {
+        IndexAction::Add { dir: source, name } => {
+            let name = match name {
+                Some(name) => name,
+                None => source
+                    .canonicalize()
+                    .with_context(|| format!("Failed to open {}", source.display()))?
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .context("Cannot derive an index name from the directory, use --name")?,
+            };
+            let (files, chunks) = core.index_add(&name, &source).await?;
+            if files == 0 {
+                println!("Index `{}` is up to date", name);
+            } else {
+                println!("Indexed {} file(s) as {} chunk(s) into `{}`", files, chunks, name);
+            }
+        }
+        IndexAction::List => {
+            let mut indexes = Vec::new();
+            for name in Index::list(&dir)? {
+                if let Some(index) = Index::load(&dir, &name)? {
+                    indexes.push(serde_json::json!({
+                        "name": name,
+                        "model": index.model,
+                        "files": index.files.len(),
+                        "chunks": index.chunks.len(),
+                    }));
+                }
+            }
+            if json {
+                println!("{}", serde_json::to_string_pretty(&indexes)?);
+            } else if indexes.is_empty() {
+                println!("No indexes");
+            } else {
+                for index in indexes {
+                    println!(
+                        "{} ({} files, {} chunks, {})",
+                        index["name"].as_str().unwrap_or_default(),
+                        index["files"],
+                        index["chunks"],
+                        index["model"].as_str().unwrap_or_default()
+                    );
+                }
+            }
+        }
+        IndexAction::Remove { name } => {
+            Index::remove(&dir, &name)?;
+            println!("Index `{}` removed", name);
+        }
+    }
+    Ok(())
+}
+
+/// `sources` 是回答引用的文档片段，文本输出时列在回答后面，JSON 输出时放在 `sources` 字段
+async fn chat(core: &mut Core, input: &TurnInput, output: Output, sources: &[Hit]) -> Result<Turn> {
+    core.set_confirm(Box::new(|name, arguments| {
+        code::confirm(&format!("Run tool `{}` with {}?", name, preview_arguments(arguments)))
+    }));
+    // 要求 JSON 回答时不流式输出，校验通过（或重答）后才输出最终的回答
+    let structured = core.structured_output().is_some();
+    let mut renderer = match output {
+        Output::Text(mode) if !structured => Some(Renderer::new(mode, io::stdout())),
+        Output::Text(_) | Output::Json | Output::Jsonl => None,
+    };
+    let mut in_reasoning = false;
+    // 流式输出时提示等回答结束后再输出，不和回答混在一行
+    let mut warnings = Vec::new();
+    if output == Output::Jsonl && !sources.is_empty() {
+        let event = serde_json::json!({ "type": "sources", "sources": sources });
+        println!("{}", serde_json::to_string(&event)?);
+    }
+    // 推理模型在第一个字出现前可能要思考很久，先给个提示
+    if let Some(renderer) = renderer.as_mut() {
+        if core.get_config().model_config().is_some_and(|model| model.reasoning) {
+            renderer.note("Thinking…")?;
+        }
+    }
+
+    let result = core
+        .chat_stream_with(input, |event| {
+            if let (ChatEvent::Warning { message }, false) = (event, output == Output::Jsonl) {
+                if renderer.is_some() {
+                    warnings.push(message.clone());
+                } else {
+                    eprintln!("Warning: {}", message);
+                }
+            } else if let Some(renderer) = renderer.as_mut() {
+                match event {
+                    ChatEvent::Reasoning { text } => {
+                        renderer.push(text)?;
+                        in_reasoning = true;
+                    }
+                    ChatEvent::Content { text } => {
+                        // 思考内容和正式回答之间空一行
+                        if in_reasoning {
+                            renderer.push("\n\n")?;
+                            in_reasoning = false;
+                        }
+                        renderer.push(text)?;
+                    }
+                    ChatEvent::ToolCall { name, arguments, .. } => {
+                        renderer.note(&format!("⚙ {} {}", name, preview_arguments(arguments)))?;
+                        in_reasoning = false;
+                    }
+                    ChatEvent::ToolResult { output, .. } => {
+                        renderer.note(&format!("  ↳ {} line(s) of output", output.lines().count()))?;
+                    }
+                    ChatEvent::Usage(_) | ChatEvent::Retry { .. } | ChatEvent::Warning { .. } | ChatEvent::Done(_) => {}
+                }
+            } else if let (ChatEvent::Retry { reason }, Output::Text(_)) = (event, output) {
+                eprintln!("Retrying: {}", reason);
+            } else if output == Output::Jsonl {
+                let mut stdout = io::stdout().lock();
+                writeln!(stdout, "{}", serde_json::to_string(event)?)?;
+                stdout.flush()?;
+            }
+            Ok(())
+        })
+        .await;
+
+    if let Some(renderer) = renderer.as_mut() {
+        renderer.finish()?;
+        println!();
+    }
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    let turn = result?;
+    if output == Output::Json {
+        let mut value = serde_json::to_value(&turn)?;
+        if !sources.is_empty() {
+            value["sources"] = serde_json::to_value(sources)?;
+        }
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    } else if matches!(output, Output::Text(_)) {
+        if structured {
+            println!("{}", turn.answer);
+        }
+        if !sources.is_empty() {
+            println!("Sources:");
+            for (i, hit) in sources.iter().enumerate() {
+                println!("[{}] {}:{}-{}", i + 1, hit.path.display(), hit.start_line, hit.end_line);
+            }
+        }
+    }
+    Ok(turn)
+}
+
+fn usage_report(config: &Config, by: GroupBy, since: Option<chrono::NaiveDate>, json: bool) -> Result<()> {
+    let records = usage::load(&config.usage_path())?;
+    let (today, month) = usage::spent(&records);
+    let selected: Vec<_> = records
+        .iter()
+        .filter(|record| since.is_none_or(|since| record.time.date_naive() >= since))
+        .collect();
+    let groups = usage::summarize(selected.iter().copied(), by);
+    let total = usage::total(selected.iter().copied());
+
+    if json {
+        let report = serde_json::json!({
+            "groups": groups,
+            "total": total,
+            "spent_today": today,
+            "spent_this_month": month,
+            "budget": config.budget,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    if groups.is_empty() {
+        println!("No usage recorded");
+        return Ok(());
+    }
+    let label = format!("{:?}", by).to_lowercase();
+    let width = groups.keys().map(|key| key.chars().count()).chain([label.len(), 5]).max().unwrap_or(5);
+    println!("{:<width$}  {:>8}  {:>10}  {:>10}  {:>10}", label, "requests", "prompt", "completion", "cost");
+    for (key, totals) in groups.iter().chain([(&"total".to_string(), &total)]) {
+        println!(
+            "{:<width$}  {:>8}  {:>10}  {:>10}  {:>10.4}",
+            key, totals.requests, totals.prompt_tokens, totals.completion_tokens, totals.cost
+        );
+    }
+    if total.unpriced > 0 {
+        println!("{} request(s) have no cost: set [models.<alias>.pricing] for their model", total.unpriced);
+    }
+
+    let budget = &config.budget;
+    let limit = |warn: Option<f64>, limit: Option<f64>| match (warn, limit) {
+        (_, Some(limit)) => format!(" / {:.4}", limit),
+        (Some(warn), None) => format!(" / {:.4} (warn)", warn),
+        (None, None) => String::new(),
+    };
+    println!();
+    println!("Spent today: {:.4}{}", today, limit(budget.daily_warn, budget.daily_limit));
+    println!("Spent this month: {:.4}{}", month, limit(budget.monthly_warn, budget.monthly_limit));
+    Ok(())
+}
+
+/// 由输出文件的扩展名决定语音合成的音频格式
+fn audio_format(path: &Path) -> Result<&'static str> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "mp3" => Ok("mp3"),
+        "wav" => Ok("wav"),
+        "opus" => Ok("opus"),
+        "pcm" => Ok("pcm"),
+        _ => bail!("Unsupported audio file {} (use .mp3, .wav, .opus or .pcm)", path.display()),
+    }
+}
+
+/// 截断过长的文本用于列表显示
+fn summarize(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    format!("{}…", text.chars().take(max_chars).collect::<String>())
+}
+
+fn parse_size(text: &str) -> Result<String, String> {
+    match text.split_once('x') {
+        Some((width, height)) if width.parse::<u32>().is_ok() && height.parse::<u32>().is_ok() => Ok(text.to_string()),
+        _ => Err(format!("expected WIDTHxHEIGHT, got `{}`", text)),
+    }
+}
+
+fn parse_key_value(text: &str) -> Result<(String, String), String> {
+    text.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", text))
+}
+
+fn mcp_list(core: &mut Core, json: bool) -> Result<()> {
+    if core.get_config().mcp_servers.is_empty() {
+        println!("No MCP servers configured");
+        return Ok(());
+    }
+    let mut servers = serde_json::Map::new();
+    for client in core.connect_mcp() {
+        let tools = client.list_tools()?;
+        let resources = client.list_resources()?;
+        let prompts = client.list_prompts()?;
+        if json {
+            servers.insert(
+                client.name().to_string(),
+                serde_json::json!({ "tools": tools, "resources": resources, "prompts": prompts }),
+            );
+            continue;
+        }
+        println!("{}", client.name());
+        for tool in &tools {
+            println!("  tool      {}  {}", mcp::tool_name(client.name(), &tool.name), tool.description.as_deref().unwrap_or(""));
+        }
+        for resource in &resources {
+            println!("  resource  {}  {}", resource.uri, resource.name);
+        }
+        for prompt in &prompts {
+            println!("  prompt    {}:{}  {}", client.name(), prompt.name, prompt.description.as_deref().unwrap_or(""));
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&servers)?);
+    }
+    Ok(())
+}
+
+/// 显示工具参数时截短过长的字符串（如 `write_file` 的文件内容）
+fn preview_arguments(arguments: &serde_json::Value) -> serde_json::Value {
+    const MAX_CHARS: usize = 200;
+    match arguments {
+        serde_json::Value::String(text) if text.chars().count() > MAX_CHARS => {
+            let head: String = text.chars().take(MAX_CHARS).collect();
+            format!("{}… ({} chars)", head, text.chars().count()).into()
+        }
+        serde_json::Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| (key.clone(), preview_arguments(value)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        other => other.clone(),
+    }
+}
+
+fn format_usage(usage: Option<&Usage>) -> String {
+    let Some(usage) = usage else {
+        return "tokens: not reported by the provider".to_string();
+    };
+    let reasoning = match usage.reasoning_tokens() {
+        0 => String::new(),
+        n => format!(" ({} reasoning)", n),
+    };
+    format!(
+        "tokens: {} prompt + {} completion{} = {} total",
+        usage.prompt_tokens, usage.completion_tokens, reasoning, usage.total_tokens
+    )
+}
+
+async fn run_config(core: &mut Core, action: ConfigAction, json: bool) -> Result<()> {
+    match action {
+        ConfigAction::Get { key, show_origin, reveal } => {
+            let config = core.get_config();
+            let keys = match &key {
+                Some(key) => {
+                    config.get(key)?;
+                    vec![key.clone()]
+                }
+                None => config.keys()?,
+            };
+
+            if json {
+                let mut object = serde_json::Map::new();
+                for key in &keys {
+                    let value = config.get(key)?.map(|value| if reveal { value } else { redact(key, value) });
+                    let mut value = serde_json::to_value(value)?;
+                    if show_origin {
+                        value = serde_json::json!({
+                            "value": value,
+                            "origin": config.origin(key).to_string(),
+                        });
+                    }
+                    object.insert(key.clone(), value);
+                }
+                println!("{}", serde_json::to_string_pretty(&object)?);
+                return Ok(());
+            }
+
+            let indent = if key.is_none() {
+                println!("Current configuration:");
+                "  "
+            } else {
+                ""
+            };
+            for key in &keys {
+                let value = match config.get(key)?.map(|value| if reveal { value } else { redact(key, value) }) {
+                    None => "None".to_string(),
+                    Some(toml::Value::String(s)) => s,
+                    Some(value) => value.to_string(),
+                };
+                if show_origin {
+                    println!("{}{}: {}  ({})", indent, key, value, config.origin(key));
+                } else {
+                    println!("{}{}: {}", indent, key, value);
+                }
+            }
+        }
+        ConfigAction::Set { key, value, force } => {
+            if is_model_key(&key) && !force {
+                let mut checker = Core::with_config(model_check_config(core.get_config(), &key));
+                let checked = check_model(&mut checker, &value).await;
+                print_warnings(&mut checker);
+                checked?;
+            }
+            let new_config = core.get_config().with_value(&key, &value)?;
+            core.update_config(new_config)?;
+            println!("Configuration updated successfully");
+        }
+        ConfigAction::Unset { key } => {
+            let new_config = core.get_config().without_value(&key)?;
+            core.update_config(new_config)?;
+            println!("{} reset to default", key);
+        }
+        ConfigAction::Edit => {
+            edit_config(core.get_config())?;
+            println!("Configuration updated successfully");
+        }
+        ConfigAction::Keyring { token } => {
+            let token = match token {
+                Some(token) => token,
+                None => {
+                    let mut token = String::new();
+                    io::stdin().read_line(&mut token)?;
+                    token.trim().to_string()
+                }
+            };
+            if token.is_empty() {
+                bail!("No token given");
+            }
+
+            let mut new_config = core.get_config().clone();
+            secret::store_in_keyring(&new_config.keyring_account(), &token)?;
+            new_config.token_keyring = true;
+            new_config.token = String::new();
+            core.update_config(new_config)?;
+            println!("Token stored in the system keyring");
+        }
+    }
+    Ok(())
+}
+
+/// 对照服务商的模型列表检查模型名，拼错时给出相近的 ID；取不到列表时只提示，不阻止设置
+/// 值是模型名的配置项：`model`、`profiles.<name>.model` 和 `models.<alias>.id`
+fn is_model_key(key: &str) -> bool {
+    let parts: Vec<&str> = key.split('.').collect();
+    matches!(parts.as_slice(), ["model"] | ["profiles", _, "model"] | ["models", _, "id"])
+}
+
+/// 检查或补全 `key` 的模型名时使用的配置：profile 的模型按该 profile 的服务商，别名的 ID 按别名的 `provider`
+fn model_check_config(config: &Config, key: &str) -> Config {
+    match key.split('.').collect::<Vec<_>>().as_slice() {
+        ["profiles", profile, "model"] => Config::load_with_profile(Some(config.path()), Some(profile))
+            .unwrap_or_else(|_| config.clone()),
+        ["models", alias, "id"] => {
+            let mut config = config.clone();
+            if config.models.contains_key(*alias) {
+                config.override_model(alias);
+            }
+            config
+        }
+        _ => config.clone(),
+    }
+}
+
+async fn check_model(core: &mut Core, model: &str) -> Result<()> {
+    if core.get_config().models.contains_key(model) {
+        return Ok(());
+    }
+    let list = match core.models(false).await {
+        Ok(list) => list,
+        Err(e) => {
+            eprintln!("Could not check the model against the provider's list: {:#}", e);
+            return Ok(());
+        }
+    };
+    if list.is_empty() || list.iter().any(|m| m.id == model) {
+        return Ok(());
+    }
+    let suggestions = models::suggest(model, &list);
+    if suggestions.is_empty() {
+        bail!("Unknown model `{}` (see `chat models list`, or pass --force to set it anyway)", model);
+    }
+    bail!(
+        "Unknown model `{}`. Did you mean:\n  {}\n(pass --force to set it anyway)",
+        model,
+        suggestions.join("\n  ")
+    )
+}
+
+fn complete_config_key(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    let keys = completion_config().and_then(|config| config.keys().ok()).unwrap_or_default();
+    keys.into_iter()
+        .filter(|key| key.starts_with(current.as_ref()))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// `config set model <TAB>`（以及 `profiles.<name>.model`、`models.<alias>.id`）同 `-m <TAB>`
+fn complete_config_value(current: &OsStr) -> Vec<CompletionCandidate> {
+    // 补全引擎只给出当前单词，要补全哪个键得从完整的命令行里看
+    let args: Vec<String> = std::env::args().collect();
+    let Some(key) = args.len().checked_sub(2).map(|i| args[i].as_str()).filter(|key| is_model_key(key)) else {
+        return Vec::new();
+    };
+    match completion_config() {
+        Some(config) => model_candidates(&model_check_config(&config, key), current),
+        None => Vec::new(),
+    }
+}
+
+/// 补全时和主命令一样加载配置：命令行上已有的 `--config`、`--profile` 优先，其次是 `CHAT_CONFIG`
+///
+/// 正在补全的 profile 名可能还不完整，加载失败时退回不带 profile 的配置。
+fn completion_config() -> Option<Config> {
+    let args: Vec<String> = std::env::args().collect();
+    let flag = |name: &str| {
+        let prefix = format!("{}=", name);
+        args.iter().enumerate().rev().find_map(|(i, arg)| match arg.strip_prefix(&prefix) {
+            Some(value) => Some(value.to_string()),
+            None if arg == name => args.get(i + 1).cloned(),
+            None => None,
+        })
+    };
+    let path = flag("--config").map(PathBuf::from);
+    let profile = flag("--profile");
+    Config::load_with_profile(path.as_deref(), profile.as_deref())
+        .or_else(|_| Config::load_from(path.as_deref()))
+        .ok()
+}
+
+/// 补全模型别名和缓存中的模型 ID；补全时不发网络请求
+fn complete_model(current: &OsStr) -> Vec<CompletionCandidate> {
+    match completion_config() {
+        Some(config) => model_candidates(&config, current),
+        None => Vec::new(),
+    }
+}
+
+fn model_candidates(config: &Config, current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    let ids = models::cached(config.effective_api_base(), Duration::MAX)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|model| model.id);
+    config
+        .models
+        .keys()
+        .cloned()
+        .chain(ids)
+        .filter(|id| id.starts_with(current.as_ref()))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+fn complete_profile(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    let config = completion_config().unwrap_or_default();
+    config
+        .profiles
+        .keys()
+        .filter(|name| name.starts_with(current.as_ref()))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// token 和 MCP 服务器的环境变量（常用来传 API key）都按密钥处理
+fn is_secret(key: &str) -> bool {
+    let parts: Vec<&str> = key.split('.').collect();
+    parts.last() == Some(&"token") || (parts.first() == Some(&"mcp_servers") && parts.get(2) == Some(&"env"))
+}
+
+/// 隐藏 `value` 中的密钥；表会逐项检查，`config get mcp_servers` 同样不会显示明文
+fn redact(key: &str, value: toml::Value) -> toml::Value {
+    match value {
+        toml::Value::String(s) if is_secret(key) => toml::Value::String(secret::mask(&s)),
+        toml::Value::Table(table) => toml::Value::Table(
+            table
+                .into_iter()
+                .map(|(name, value)| {
+                    let path = if key.is_empty() { name.clone() } else { format!("{}.{}", key, name) };
+                    let value = redact(&path, value);
+                    (name, value)
+                })
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// 在编辑器中修改配置文件的副本，校验通过后才替换原文件
+fn edit_config(config: &Config) -> Result<()> {
+    let path = config.path();
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("$EDITOR is empty")?;
+    let args: Vec<&str> = words.collect();
+
+    let draft = path.with_extension("toml.edit");
+    std::fs::copy(path, &draft).context("Failed to copy config file")?;
+
+    loop {
+        let status = std::process::Command::new(program)
+            .args(&args)
+            .arg(&draft)
+            .status()
+            .with_context(|| format!("Failed to start editor `{}`", editor))?;
+        if !status.success() {
+            let _ = std::fs::remove_file(&draft);
+            bail!("Editor exited with {}, config not changed", status);
+        }
+
+        let content = std::fs::read_to_string(&draft)?;
+        match Config::validate_content(&content) {
+            Ok(()) => {
+                config.save_content(&content)?;
+                let _ = std::fs::remove_file(&draft);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Invalid config: {:#}", e);
+                if !code::confirm("Edit again?")? {
+                    let _ = std::fs::remove_file(&draft);
+                    bail!("Config not changed");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_flags_work_after_subcommands() {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["chat", "ask", "--docs", "notes", "--jsonl", "why?"]).unwrap();
+        assert!(cli.jsonl && !cli.json);
+        let cli = Cli::try_parse_from(["chat", "ask", "--docs", "notes", "--json", "why?"]).unwrap();
+        assert!(cli.json && !cli.jsonl);
+        assert!(Cli::try_parse_from(["chat", "ask", "--docs", "notes", "--json", "--jsonl", "why?"]).is_err());
+    }
+
+    #[test]
+    fn model_keys_are_recognized() {
+        for key in ["model", "profiles.work.model", "models.fast.id"] {
+            assert!(is_model_key(key), "{}", key);
+        }
+        for key in ["embedding_model", "profiles.work.speech_model", "models.fast.provider", "models.id", "profiles.model"] {
+            assert!(!is_model_key(key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn alias_ids_are_checked_against_their_provider() {
+        let mut config = Config::default();
+        config.models.insert(
+            "other".to_string(),
+            toml::from_str("id = \"x\"\nprovider = \"second\"").unwrap(),
+        );
+        config.providers.insert(
+            "second".to_string(),
+            toml::from_str("api_base = \"https://second.example/v1/chat/completions\"").unwrap(),
+        );
+        let checked = model_check_config(&config, "models.other.id");
+        assert_eq!(checked.effective_api_base(), "https://second.example/v1/chat/completions");
+        assert_eq!(model_check_config(&config, "models.new.id").effective_api_base(), config.api_base);
+    }
+
+    #[test]
+    fn redact_masks_tokens_and_mcp_env() {
+        let config: toml::Value = toml::from_str(
+            r#"
+            token = "sk-0123456789abcdef"
+            model = "gpt-4o"
+
+            [mcp_servers.search]
+            command = "search-mcp"
+            env = { SEARCH_API_KEY = "key-0123456789abcdef" }
+
+            [providers.work]
+            token = "sk-fedcba9876543210"
+            "#,
+        )
+        .unwrap();
+        let shown = redact("", config).to_string();
+        assert!(!shown.contains("0123456789abcdef"), "{}", shown);
+        assert!(!shown.contains("fedcba9876543210"), "{}", shown);
+        assert!(shown.contains("gpt-4o") && shown.contains("search-mcp"));
+
+        let env = toml::Value::Table(toml::toml! { SEARCH_API_KEY = "key-0123456789abcdef" });
+        assert_eq!(redact("mcp_servers.search.env", env)["SEARCH_API_KEY"].as_str(), Some("key****cdef"));
+    }
+}
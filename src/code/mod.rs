@@ -0,0 +1,250 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone)]
+pub struct CodeBlock {
+    pub lang: String,
+    pub code: String,
+}
+
+impl CodeBlock {
+    /// 代码的第一行非空内容，用于列表展示
+    pub fn summary(&self) -> &str {
+        self.code
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or("")
+    }
+}
+
+/// 从 markdown 文本中提取所有围栏代码块
+///
+/// 代码块的缩进以开头围栏的缩进为准去除，因此列表中的代码块也能得到正确的内容。
+/// 未闭合的代码块（例如回答被截断）同样会被提取。
+pub fn extract(markdown: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(String, usize, CodeBlock)> = None;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+
+        if let Some((fence, fence_indent, block)) = current.as_mut() {
+            let closing = trimmed.trim_end();
+            if closing.len() >= fence.len() && closing.chars().all(|c| fence.starts_with(c)) {
+                blocks.push(current.take().unwrap().2);
+                continue;
+            }
+            let strip = line
+                .chars()
+                .take(*fence_indent)
+                .take_while(|c| *c == ' ')
+                .count();
+            block.code.push_str(&line[strip..]);
+            block.code.push('\n');
+            continue;
+        }
+
+        for marker in ['`', '~'] {
+            let count = trimmed.chars().take_while(|c| *c == marker).count();
+            if count >= 3 {
+                let lang = trimmed[count..].split_whitespace().next().unwrap_or("");
+                current = Some((
+                    marker.to_string().repeat(count),
+                    indent,
+                    CodeBlock {
+                        lang: lang.to_string(),
+                        code: String::new(),
+                    },
+                ));
+                break;
+            }
+        }
+    }
+
+    if let Some((_, _, block)) = current {
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// 按 1 开始的序号取代码块
+pub fn nth(blocks: &[CodeBlock], n: usize) -> Result<&CodeBlock> {
+    if blocks.is_empty() {
+        bail!("No code blocks in the last answer");
+    }
+    n.checked_sub(1)
+        .and_then(|i| blocks.get(i))
+        .ok_or_else(|| anyhow!("Code block {} does not exist (1-{})", n, blocks.len()))
+}
+
+pub fn save(block: &CodeBlock, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).context("Failed to create directory")?;
+    }
+    std::fs::write(path, &block.code)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// 复制到系统剪贴板，依次尝试各平台常见的剪贴板命令
+pub fn copy(block: &CodeBlock) -> Result<()> {
+    let candidates: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+        &[("pbcopy", &[])]
+    } else if cfg!(windows) {
+        &[("clip", &[])]
+    } else {
+        &[
+            ("wl-copy", &[]),
+            ("xclip", &["-selection", "clipboard"]),
+            ("xsel", &["--clipboard", "--input"]),
+        ]
+    };
+
+    for (program, args) in candidates {
+        let child = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            continue;
+        };
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(block.code.as_bytes())?;
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+
+    let names: Vec<&str> = candidates.iter().map(|(program, _)| *program).collect();
+    bail!("No clipboard tool available (tried: {})", names.join(", "))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpreter {
+    Shell,
+    Python,
+}
+
+impl Interpreter {
+    pub fn from_lang(lang: &str) -> Option<Self> {
+        match lang.to_lowercase().as_str() {
+            "sh" | "bash" | "zsh" | "shell" => Some(Interpreter::Shell),
+            "py" | "python" | "python3" => Some(Interpreter::Python),
+            _ => None,
+        }
+    }
+
+    fn command(&self) -> Command {
+        match self {
+            Interpreter::Shell if cfg!(windows) => {
+                let mut command = Command::new("cmd");
+                command.arg("/C");
+                command
+            }
+            Interpreter::Shell => {
+                let mut command = Command::new("sh");
+                command.arg("-c");
+                command
+            }
+            Interpreter::Python => {
+                let mut command = Command::new(if cfg!(windows) { "python" } else { "python3" });
+                command.arg("-c");
+                command
+            }
+        }
+    }
+}
+
+/// 运行代码块，返回进程退出码
+pub fn run(block: &CodeBlock, interpreter: Interpreter) -> Result<i32> {
+    let status = interpreter
+        .command()
+        .arg(&block.code)
+        .status()
+        .context("Failed to start interpreter")?;
+    Ok(status.code().unwrap_or(-1))
+}
+
+/// 在终端询问用户是否继续，只有输入 y/yes 时返回 true
+pub fn confirm(prompt: &str) -> Result<bool> {
+    // 提示写到 stderr，不混进重定向的输出里
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(markdown: &str) -> Vec<(String, String)> {
+        extract(markdown).into_iter().map(|block| (block.lang, block.code)).collect()
+    }
+
+    #[test]
+    fn extracts_language_tags() {
+        let markdown = "Run this:\n\n```bash title=\"x\"\necho hi\n```\n\nthen\n\n```\nplain\n```\n";
+        assert_eq!(
+            blocks(markdown),
+            [("bash".to_string(), "echo hi\n".to_string()), (String::new(), "plain\n".to_string())]
+        );
+    }
+
+    #[test]
+    fn handles_tilde_and_nested_fences() {
+        let markdown = "~~~python\nprint('```')\n~~~\n\n````md\n```rust\nfn main() {}\n```\n````\n";
+        assert_eq!(
+            blocks(markdown),
+            [
+                ("python".to_string(), "print('```')\n".to_string()),
+                ("md".to_string(), "```rust\nfn main() {}\n```\n".to_string()),
+            ]
+        );
+        // 较短的或不同字符的围栏不会闭合代码块，更长的可以
+        assert_eq!(blocks("```\n~~~\n``\n`````\nafter\n"), [(String::new(), "~~~\n``\n".to_string())]);
+    }
+
+    #[test]
+    fn strips_the_indentation_of_the_fence() {
+        let markdown = "1. Install:\n\n   ```sh\n   cargo install chat\n     --locked\n  short\n   ```\n";
+        assert_eq!(blocks(markdown), [("sh".to_string(), "cargo install chat\n  --locked\nshort\n".to_string())]);
+    }
+
+    #[test]
+    fn keeps_unterminated_blocks() {
+        assert_eq!(blocks("```py\nprint(1)\n"), [("py".to_string(), "print(1)\n".to_string())]);
+        assert!(blocks("no code here, only ``inline`` code").is_empty());
+    }
+
+    #[test]
+    fn nth_is_one_based_and_checked() {
+        let blocks = extract("```\none\n```\n```\ntwo\n```\n");
+        assert_eq!(nth(&blocks, 1).unwrap().code, "one\n");
+        assert_eq!(nth(&blocks, 2).unwrap().code, "two\n");
+        assert_eq!(nth(&blocks, 3).unwrap_err().to_string(), "Code block 3 does not exist (1-2)");
+        assert!(nth(&blocks, 0).is_err());
+        assert_eq!(nth(&[], 1).unwrap_err().to_string(), "No code blocks in the last answer");
+    }
+
+    #[test]
+    fn summary_and_interpreters() {
+        let block = CodeBlock {
+            lang: "SH".to_string(),
+            code: "\n  \n  ls -la\npwd\n".to_string(),
+        };
+        assert_eq!(block.summary(), "ls -la");
+        assert_eq!(Interpreter::from_lang(&block.lang), Some(Interpreter::Shell));
+        assert_eq!(Interpreter::from_lang("python3"), Some(Interpreter::Python));
+        assert_eq!(Interpreter::from_lang("rust"), None);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use toml::{Table, Value};
+
+/// 配置值的来源，优先级从低到高
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    Global(PathBuf),
+    Project(PathBuf),
+    Profile(String),
+    Env(String),
+    /// 命令行参数，只对本次调用生效
+    Flag(String),
+}
+
+impl Origin {
+    /// 是否来自全局配置文件之外、不应被写回全局配置文件的层
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Origin::Project(_) | Origin::Profile(_) | Origin::Env(_) | Origin::Flag(_))
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::Global(path) => write!(f, "global: {}", path.display()),
+            Origin::Project(path) => write!(f, "project: {}", path.display()),
+            Origin::Profile(name) => write!(f, "profile: {}", name),
+            Origin::Env(name) => write!(f, "env: {}", name),
+            Origin::Flag(flag) => write!(f, "flag: {}", flag),
+        }
+    }
+}
+
+/// 将 `layer` 合并进 `base`：表递归合并，其余值直接覆盖，并记录每个叶子键（点分路径）的来源
+pub fn merge(base: &mut Table, layer: Table, origin: &Origin, origins: &mut BTreeMap<String, Origin>) {
+    merge_at(base, layer, origin, origins, "");
+}
+
+fn merge_at(
+    base: &mut Table,
+    layer: Table,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+    prefix: &str,
+) {
+    for (key, value) in layer {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(table)) => {
+                merge_at(base_table, table, origin, origins, &path);
+            }
+            (_, value) => {
+                record(&value, &path, origin, origins);
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn record(value: &Value, path: &str, origin: &Origin, origins: &mut BTreeMap<String, Origin>) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                record(value, &format!("{}.{}", path, key), origin, origins);
+            }
+        }
+        _ => {
+            origins.insert(path.to_string(), origin.clone());
+        }
+    }
+}
+
+/// 按点分路径读取
+pub fn get_path<'a>(table: &'a Table, path: &str) -> Option<&'a Value> {
+    let mut parts = path.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+    Some(value)
+}
+
+/// 按点分路径写入，中间缺少的表会自动创建
+pub fn set_path(table: &mut Table, path: &str, value: Value) {
+    match path.split_once('.') {
+        None => {
+            table.insert(path.to_string(), value);
+        }
+        Some((head, rest)) => {
+            let entry = table
+                .entry(head.to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            set_path(entry.as_table_mut().expect("entry is a table"), rest, value);
+        }
+    }
+}
+
+/// 按点分路径删除，返回被删除的值
+pub fn remove_path(table: &mut Table, path: &str) -> Option<Value> {
+    match path.split_once('.') {
+        None => table.remove(path),
+        Some((head, rest)) => remove_path(table.get_mut(head)?.as_table_mut()?, rest),
+    }
+}
+
+/// 所有叶子键的点分路径（数组算作叶子）
+pub fn leaves(table: &Table) -> Vec<String> {
+    let mut leaves = Vec::new();
+    collect_leaves(table, "", &mut leaves);
+    leaves
+}
+
+fn collect_leaves(table: &Table, prefix: &str, leaves: &mut Vec<String>) {
+    for (key, value) in table {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            Value::Table(child) => collect_leaves(child, &path, leaves),
+            _ => leaves.push(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(content: &str) -> Table {
+        content.parse().unwrap()
+    }
+
+    #[test]
+    fn merge_overrides_leaves_and_records_origins() {
+        let global = Origin::Global(PathBuf::from("config.toml"));
+        let project = Origin::Project(PathBuf::from(".chat.toml"));
+        let mut base = table("model = \"a\"\n[budget]\ndaily_limit = 1.0\nmonthly_limit = 10.0");
+        let mut origins = BTreeMap::new();
+        merge(&mut base, table("[budget]\ndaily_limit = 2.0\n[models.fast]\nid = \"x\""), &global, &mut origins);
+        merge(&mut base, table("model = \"b\"\nbudget = 3\ntools = [\"read_file\"]"), &project, &mut origins);
+
+        assert_eq!(base, table("model = \"b\"\nbudget = 3\ntools = [\"read_file\"]\n[models.fast]\nid = \"x\""));
+        let expected = [
+            ("budget", &project),
+            ("budget.daily_limit", &global),
+            ("model", &project),
+            ("models.fast.id", &global),
+            ("tools", &project),
+        ];
+        let origins: Vec<(&str, &Origin)> = origins.iter().map(|(key, origin)| (key.as_str(), origin)).collect();
+        assert_eq!(origins, expected);
+    }
+
+    #[test]
+    fn paths_are_read_written_and_removed() {
+        let mut config = table("model = \"a\"\n[models.fast]\nid = \"x\"");
+        set_path(&mut config, "models.fast.provider", "p".into());
+        set_path(&mut config, "budget.daily_limit", 1.5.into());
+        // 路径上不是表的值会被替换成表
+        set_path(&mut config, "model.id", "b".into());
+        assert_eq!(get_path(&config, "models.fast.provider"), Some(&Value::from("p")));
+        assert_eq!(get_path(&config, "budget.daily_limit"), Some(&Value::from(1.5)));
+        assert_eq!(get_path(&config, "model.id"), Some(&Value::from("b")));
+        assert_eq!(get_path(&config, "models.slow.id"), None);
+
+        assert_eq!(remove_path(&mut config, "models.fast.id"), Some(Value::from("x")));
+        assert_eq!(remove_path(&mut config, "models.fast.id"), None);
+        assert_eq!(remove_path(&mut config, "budget.daily_limit.x"), None);
+        assert_eq!(remove_path(&mut config, "budget"), Some(Value::Table(table("daily_limit = 1.5"))));
+        assert_eq!(config, table("[model]\nid = \"b\"\n[models.fast]\nprovider = \"p\""));
+    }
+
+    #[test]
+    fn leaves_are_dotted_paths() {
+        let config = table("model = \"a\"\ntools = [\"x\", \"y\"]\n[empty]\n[models.fast]\nid = \"x\"\nprice = { input = 1.0 }");
+        assert_eq!(leaves(&config), ["model", "tools", "models.fast.id", "models.fast.price.input"]);
+    }
+
+    #[test]
+    fn only_the_global_file_is_persistent() {
+        assert!(!Origin::Default.is_transient());
+        assert!(!Origin::Global(PathBuf::from("config.toml")).is_transient());
+        assert!(Origin::Project(PathBuf::from(".chat.toml")).is_transient());
+        assert!(Origin::Profile("work".to_string()).is_transient());
+        assert!(Origin::Env("CHAT_MODEL".to_string()).is_transient());
+        assert!(Origin::Flag("--model".to_string()).is_transient());
+        assert_eq!(Origin::Env("CHAT_MODEL".to_string()).to_string(), "env: CHAT_MODEL");
+    }
+}
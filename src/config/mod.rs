@@ -1,98 +1,889 @@
-use std::{fs, path::Path};
+mod layers;
+mod schema;
+pub mod secret;
+pub(crate) mod store;
+
+pub use layers::Origin;
+
+use crate::bridge::Usage;
+use std::{env, fs, path::Path};
 use std::path::PathBuf;
-use anyhow::{Result, Context};
+use anyhow::{bail, Result, Context};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
+use toml::{Table, Value};
+
+/// 项目级配置文件名，从当前目录向上查找
+pub const PROJECT_CONFIG_NAME: &str = ".chat.toml";
+
+/// 项目级配置不允许覆盖的键：仓库里的配置文件不应能把 token 发往别的服务器，也不应能执行命令
+const PROJECT_FORBIDDEN_KEYS: &[&str] = &["api_base", "token", "token_env", "token_command", "token_keyring", "profiles", "providers", "tools", "allowed_commands", "mcp_servers"];
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+const DEFAULT_TRANSCRIPTION_MODEL: &str = "FunAudioLLM/SenseVoiceSmall";
+const DEFAULT_SPEECH_MODEL: &str = "FunAudioLLM/CosyVoice2-0.5B";
+const DEFAULT_IMAGE_MODEL: &str = "black-forest-labs/FLUX.1-schnell";
+const DEFAULT_EMBEDDING_MODEL: &str = "BAAI/bge-m3";
+const DEFAULT_RERANK_MODEL: &str = "BAAI/bge-reranker-v2-m3";
+
+/// token 的各个来源，profile 设置了其中任意一个时，其余的全局设置不再生效
+const TOKEN_KEYS: &[&str] = &["token", "token_env", "token_command", "token_keyring"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Config {
+    #[schemars(url)]
     pub api_base: String,
     pub token: String,
+    /// 从该环境变量读取 token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_env: Option<String>,
+    /// 执行该命令，以其输出作为 token，例如 `pass show siliconflow`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_command: Option<String>,
+    /// 从系统密钥环读取 token（需要 `keyring` feature）
+    #[serde(default)]
+    pub token_keyring: bool,
     pub model: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub temperature: Option<f32>,
+    #[schemars(range(min = 0.0, max = 2.0))]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
     #[serde(default = "default_max_history")]
     pub max_history: usize,
+    /// 流式请求时要求服务商返回 token 用量（`stream_options.include_usage`），不支持的服务商可以关掉
+    #[serde(default = "default_true")]
+    pub stream_usage: bool,
+    /// 模型别名及其默认参数，`model = "r1"` 或 `-m r1` 都会解析为对应的模型 ID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub models: BTreeMap<String, ModelConfig>,
+    /// 其他服务商，供 `[models.<alias>]` 的 `provider` 引用
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub providers: BTreeMap<String, Provider>,
+    /// 以 shell 命令实现、可供模型调用的工具
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tools: BTreeMap<String, ShellToolConfig>,
+    /// 启用的内置工具，只能访问当前目录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub builtin_tools: Vec<BuiltinTool>,
+    /// `run_command` 可以执行的程序
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_commands: Vec<String>,
+    /// `chat transcribe` 和 `--voice-in` 使用的语音识别模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcription_model: Option<String>,
+    /// `chat tts` 和 `--speak` 使用的语音合成模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speech_model: Option<String>,
+    /// 语音合成的音色
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voice: Option<String>,
+    /// `chat image` 使用的图片生成模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_model: Option<String>,
+    /// `chat embed` 和文档索引使用的向量模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding_model: Option<String>,
+    /// 保存每轮对话时计算其向量，供 `chat history similar` 和 `--recall` 使用；每轮多一次 `/embeddings` 请求
     #[serde(default)]
+    pub embed_history: bool,
+    /// 文档索引重排序使用的模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_model: Option<String>,
+    /// 各个文档索引的检索设置
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub indexes: BTreeMap<String, IndexConfig>,
+    /// 要连接的 MCP 服务器，其工具、资源和 prompt 都可供使用
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mcp_servers: BTreeMap<String, McpServerConfig>,
+    /// 花费预算，金额的单位同 `[models.<alias>.pricing]`
+    #[serde(default, skip_serializing_if = "Budget::is_empty")]
+    pub budget: Budget,
+    /// 用 `--profile <name>` 选择的一组配置，覆盖全局和项目配置
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// 历史记录单独保存在数据目录中；旧版配置文件里的 history 仍可读取，用于迁移
+    #[serde(default, skip_serializing)]
+    #[schemars(skip)]
     pub history: VecDeque<HistoryItem>,
+    #[serde(skip)]
+    paths: ConfigPaths,
+    #[serde(skip)]
+    layers: Layers,
+    /// 加载时产生的提示，由调用方决定如何展示
+    #[serde(skip)]
+    warnings: Vec<String>,
+}
+
+/// `[models.<alias>]`，选中该模型时这些设置优先于全局的同名配置
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ModelConfig {
+    /// 服务商使用的模型 ID
+    pub id: String,
+    /// `[providers.<name>]` 中的服务商，不设置时使用全局的 `api_base` 和 token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0.0, max = 2.0))]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// 上下文窗口大小（token），超出时丢弃最早的历史记录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<u32>,
+    /// 模型会先输出 `reasoning_content`（思考过程）
+    #[serde(default)]
+    pub reasoning: bool,
+    /// `--json-object` / `--json-schema` 时发送 `response_format`；服务商不支持时关掉，只靠系统提示约束
+    #[serde(default = "default_true")]
+    pub response_format: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<Pricing>,
+}
+
+/// 每百万 token 的价格
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct Pricing {
+    #[schemars(range(min = 0.0))]
+    pub input: f64,
+    #[schemars(range(min = 0.0))]
+    pub output: f64,
+}
+
+impl Pricing {
+    /// 按用量计算一次请求的费用
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input + usage.completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+/// `[budget]`：达到 `*_warn` 时提醒，达到 `*_limit` 时拒绝继续发送
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct Budget {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0.0))]
+    pub daily_warn: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0.0))]
+    pub daily_limit: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0.0))]
+    pub monthly_warn: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0.0))]
+    pub monthly_limit: Option<f64>,
+}
+
+impl Budget {
+    pub fn is_empty(&self) -> bool {
+        self.daily_warn.is_none()
+            && self.daily_limit.is_none()
+            && self.monthly_warn.is_none()
+            && self.monthly_limit.is_none()
+    }
+}
+
+/// `[tools.<name>]`：`command` 中的 `{参数名}` 会被替换为转义后的参数值
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ShellToolConfig {
+    pub description: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub parameters: BTreeMap<String, ToolParameter>,
+    /// 每次执行前询问
+    #[serde(default = "default_true")]
+    pub confirm: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ToolParameter {
+    #[serde(rename = "type", default)]
+    pub kind: ParameterType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// 模型可以不提供该参数，此时替换为空字符串
+    #[serde(default)]
+    pub optional: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterType {
+    #[default]
+    String,
+    Integer,
+    Number,
+    Boolean,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// 内置工具
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum BuiltinTool {
+    ReadFile,
+    ListDir,
+    SearchText,
+    /// 每次写入前确认
+    WriteFile,
+    /// 只能执行 `allowed_commands` 中的程序，每次执行前确认
+    RunCommand,
+}
+
+/// `[mcp_servers.<name>]`：以子进程启动、通过 stdio 通信的 MCP 服务器
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct McpServerConfig {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// 额外传给服务器进程的环境变量
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// 调用其工具前询问
+    #[serde(default = "default_true")]
+    pub confirm: bool,
+    /// 等待一次响应的最长秒数
+    #[serde(default = "default_mcp_timeout")]
+    pub timeout: u64,
+}
+
+/// `[indexes.<name>]`：`chat ask --docs <name>` 的检索设置
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct IndexConfig {
+    /// 向量检索后再用 `/rerank` 重新排序，只保留最相关的片段
+    #[serde(default)]
+    pub rerank: bool,
+    /// 覆盖全局的 `rerank_model`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_model: Option<String>,
+    /// 交给重排序的候选片段数，默认是最终片段数的 4 倍
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub candidates: Option<usize>,
+}
+
+/// `[providers.<name>]`：另一个服务商的地址和 token 来源
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct Provider {
+    #[schemars(url)]
+    pub api_base: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_env: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_command: Option<String>,
+    #[serde(default)]
+    pub token_keyring: bool,
+}
+
+impl Provider {
+    /// 顺序同 [`Config::resolve_token`]
+    pub fn resolve_token(&self) -> Result<String> {
+        if let Some(command) = &self.token_command {
+            return secret::token_from_command(command);
+        }
+        if let Some(name) = &self.token_env {
+            return secret::token_from_env(name);
+        }
+        if self.token_keyring {
+            return secret::token_from_keyring(&host(&self.api_base));
+        }
+        self.token
+            .clone()
+            .with_context(|| format!("No token configured for provider {}", self.api_base))
+    }
+}
+
+/// `[profiles.<name>]`，未设置的项沿用全局配置
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(url)]
+    pub api_base: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_env: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_keyring: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0.0, max = 2.0))]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcription_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speech_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voice: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub builtin_tools: Option<Vec<BuiltinTool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_commands: Option<Vec<String>>,
+}
+
+/// 加载时各层配置的信息，保存时用来还原全局配置文件本来的内容
+#[derive(Debug, Clone, Default)]
+struct Layers {
+    /// 全局配置文件的原始内容
+    global: Table,
+    /// 合并所有层之后的内容
+    merged: Table,
+    origins: BTreeMap<String, Origin>,
+    /// 本次使用的 profile
+    profile: Option<String>,
+}
+
+/// 配置文件和历史记录文件的位置
+#[derive(Debug, Clone, Default)]
+struct ConfigPaths {
+    config: PathBuf,
+    history: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct HistoryFile {
+    #[serde(default)]
+    history: VecDeque<HistoryItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct HistoryItem {
     pub question: String,
     pub answer: String,
+    /// 随问题发送的图片，只记录路径
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<PathBuf>,
+    /// 回答这一轮的模型 ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
 }
 
 fn default_max_history() -> usize {
     10
 }
 
+fn default_true() -> bool {
+    true
+}
+
+fn default_mcp_timeout() -> u64 {
+    60
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             api_base: "https://api.siliconflow.cn/v1/chat/completions".to_string(),
             token: "sk-default-token".to_string(),
+            token_env: None,
+            token_command: None,
+            token_keyring: false,
             model: "deepseek-ai/DeepSeek-V3".to_string(),
             max_tokens: None,
             temperature: None,
+            system_prompt: None,
             max_history: default_max_history(),
+            stream_usage: true,
+            models: BTreeMap::new(),
+            providers: BTreeMap::new(),
+            tools: BTreeMap::new(),
+            builtin_tools: Vec::new(),
+            allowed_commands: Vec::new(),
+            transcription_model: None,
+            speech_model: None,
+            voice: None,
+            image_model: None,
+            embedding_model: None,
+            embed_history: false,
+            rerank_model: None,
+            indexes: BTreeMap::new(),
+            mcp_servers: BTreeMap::new(),
+            budget: Budget::default(),
+            profiles: BTreeMap::new(),
             history: VecDeque::new(),
+            paths: ConfigPaths::default(),
+            layers: Layers::default(),
+            warnings: Vec::new(),
         }
     }
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
-        let config_path = get_config_path()?;
-        
-        if !config_path.exists() {
-            let config = Config::default();
-            config.save()?;
+        Self::load_from(None)
+    }
+
+    /// 按 `path` 参数、`CHAT_CONFIG` 环境变量、平台配置目录的顺序确定全局配置文件位置，
+    /// 再依次叠加项目级 `.chat.toml` 和 `CHAT_*` 环境变量
+    pub fn load_from(path: Option<&Path>) -> Result<Self> {
+        Self::load_with_profile(path, None)
+    }
+
+    /// 同 [`Config::load_from`]，并在项目配置之上、环境变量之下叠加 `[profiles.<profile>]`
+    pub fn load_with_profile(path: Option<&Path>, profile: Option<&str>) -> Result<Self> {
+        let paths = ConfigPaths {
+            config: resolve_config_path(path)?,
+            history: get_history_path()?,
+        };
 
-            println!("Config file auto created successfully. Path: {}", config_path.display());
+        let mut warnings = Vec::new();
+        if !paths.config.exists() {
+            let legacy_path = get_legacy_config_path();
+            if path.is_none() && env::var_os("CHAT_CONFIG").is_none() && legacy_path.exists() {
+                warnings.push(Self::migrate(&legacy_path, &paths)?);
+            } else {
+                let config = Config {
+                    paths: paths.clone(),
+                    ..Config::default()
+                };
+                config.save()?;
 
-            return Ok(config);
+                warnings.push(format!("Config file auto created successfully. Path: {}", config.path().display()));
+            }
         }
 
-        let content = fs::read_to_string(&config_path)
-            .context("Failed to read config file")?;
-        let config: Config = toml::from_str(&content)
+        let mut merged = Table::try_from(Config::default())
+            .context("Failed to serialize default config")?;
+        let mut origins = BTreeMap::new();
+
+        let global = read_table(&paths.config)?;
+        layers::merge(&mut merged, global.clone(), &Origin::Global(paths.config.clone()), &mut origins);
+
+        if let Some(project_path) = find_project_config() {
+            if project_path != paths.config {
+                let project = read_table(&project_path)?;
+                warnings.extend(apply_project(&mut merged, project, project_path, &mut origins));
+            }
+        }
+
+        if let Some(name) = profile {
+            apply_profile(&mut merged, name, &mut origins)?;
+        }
+
+        apply_env(&mut merged, &mut origins);
+
+        let mut config: Config = merged.clone().try_into()
             .context("Failed to parse config file")?;
-        
+        if paths.history.exists() {
+            config.history = read_history(&paths.history)?;
+        }
+        config.paths = paths;
+        config.layers = Layers {
+            global,
+            merged,
+            origins,
+            profile: profile.map(str::to_string),
+        };
+        config.warnings = warnings;
+
         Ok(config)
     }
 
-    pub fn save(&self) -> Result<()> {
-       let config_path = get_config_path()?;
-       
-       // 确保配置目录存在
-       if let Some(parent) = config_path.parent() {
-           fs::create_dir_all(parent)
-               .context("Failed to create config directory")?;
-       }
+    /// 配置文件路径
+    pub fn path(&self) -> &Path {
+        &self.paths.config
+    }
+
+    /// 历史记录文件路径
+    pub fn history_path(&self) -> &Path {
+        &self.paths.history
+    }
+
+    /// 文档索引所在的目录，和历史记录放在一起
+    pub fn index_dir(&self) -> PathBuf {
+        self.paths.history.with_file_name("indexes")
+    }
+
+    /// 历史对话向量文件路径，和历史记录放在一起
+    pub fn history_vectors_path(&self) -> PathBuf {
+        self.paths.history.with_file_name("history_vectors.jsonl")
+    }
+
+    /// 用量记录文件路径，和历史记录放在一起
+    pub fn usage_path(&self) -> PathBuf {
+        self.paths.history.with_file_name("usage.jsonl")
+    }
+
+    /// 某个配置项（点分路径）的值来自哪一层
+    pub fn origin(&self, key: &str) -> Origin {
+        self.layers
+            .origins
+            .get(key)
+            .cloned()
+            .unwrap_or(Origin::Default)
+    }
+
+    /// 取出加载时产生的提示（自动创建配置文件、忽略项目配置中的键等）
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// 本次使用的 profile
+    pub fn profile(&self) -> Option<&str> {
+        self.layers.profile.as_deref()
+    }
+
+    /// 当前模型在 `[models]` 中的设置，按别名或模型 ID 查找
+    pub fn model_config(&self) -> Option<&ModelConfig> {
+        self.models
+            .get(&self.model)
+            .or_else(|| self.models.values().find(|model| model.id == self.model))
+    }
+
+    /// 模型 ID 为 `model` 的价格：当前模型取其 `[models]` 设置，其他模型（如向量模型）按 ID 查找
+    pub fn pricing(&self, model: &str) -> Option<&Pricing> {
+        let config = if model == self.model_id() {
+            self.model_config()
+        } else {
+            self.models.values().find(|config| config.id == model)
+        };
+        config.and_then(|config| config.pricing.as_ref())
+    }
+
+    /// 实际发给服务商的模型 ID：`model` 是 `[models]` 中的别名时取其 `id`
+    pub fn model_id(&self) -> &str {
+        self.model_config()
+            .map(|model| model.id.as_str())
+            .unwrap_or(&self.model)
+    }
+
+    /// 语音识别模型，未设置时使用 SiliconFlow 上的 SenseVoice
+    pub fn transcription_model(&self) -> &str {
+        self.transcription_model.as_deref().unwrap_or(DEFAULT_TRANSCRIPTION_MODEL)
+    }
+
+    /// 语音合成模型，未设置时使用 SiliconFlow 上的 CosyVoice
+    pub fn speech_model(&self) -> &str {
+        self.speech_model.as_deref().unwrap_or(DEFAULT_SPEECH_MODEL)
+    }
+
+    /// 语音合成的音色，未设置时使用合成模型的 `alex`
+    pub fn voice(&self) -> String {
+        self.voice.clone().unwrap_or_else(|| format!("{}:alex", self.speech_model()))
+    }
+
+    /// 图片生成模型，未设置时使用 SiliconFlow 上的 FLUX.1-schnell
+    pub fn image_model(&self) -> &str {
+        self.image_model.as_deref().unwrap_or(DEFAULT_IMAGE_MODEL)
+    }
+
+    /// 向量模型，未设置时使用 SiliconFlow 上的 bge-m3
+    pub fn embedding_model(&self) -> &str {
+        self.embedding_model.as_deref().unwrap_or(DEFAULT_EMBEDDING_MODEL)
+    }
+
+    /// 索引 `name` 使用的重排序模型，未开启重排序时为 `None`
+    pub fn rerank_model(&self, name: &str) -> Option<&str> {
+        let index = self.indexes.get(name).filter(|index| index.rerank)?;
+        Some(
+            index
+                .rerank_model
+                .as_deref()
+                .or(self.rerank_model.as_deref())
+                .unwrap_or(DEFAULT_RERANK_MODEL),
+        )
+    }
+
+    /// 当前模型的 temperature，模型没有单独设置时取全局配置
+    pub fn effective_temperature(&self) -> Option<f64> {
+        self.model_config()
+            .and_then(|model| model.temperature)
+            .or(self.temperature)
+    }
+
+    pub fn effective_max_tokens(&self) -> Option<u32> {
+        self.model_config()
+            .and_then(|model| model.max_tokens)
+            .or(self.max_tokens)
+    }
+
+    /// 当前模型指定的服务商
+    fn provider(&self) -> Result<Option<&Provider>> {
+        let Some(name) = self.model_config().and_then(|model| model.provider.as_deref()) else {
+            return Ok(None);
+        };
+        self.providers.get(name).map(Some).with_context(|| {
+            format!("Model `{}` uses provider `{}`, which is not defined in [providers]", self.model, name)
+        })
+    }
+
+    /// 实际请求的服务商地址（不需要取得 token）
+    pub fn effective_api_base(&self) -> &str {
+        match self.provider() {
+            Ok(Some(provider)) => &provider.api_base,
+            _ => &self.api_base,
+        }
+    }
+
+    /// 实际请求的服务商地址和 token，当前模型指定了 `provider` 时使用该服务商
+    pub fn connection(&self) -> Result<(String, String)> {
+        match self.provider()? {
+            Some(provider) => Ok((provider.api_base.clone(), provider.resolve_token()?)),
+            None => Ok((self.api_base.clone(), self.resolve_token()?)),
+        }
+    }
+
+    /// 只对本次调用生效的模型（`-m`），不会被保存
+    pub fn override_model(&mut self, model: &str) {
+        self.model = model.to_string();
+        self.layers.merged.insert("model".to_string(), model.into());
+        self.layers.origins.insert("model".to_string(), Origin::Flag("--model".to_string()));
+    }
+
+    /// 用命令行参数替换本次启用的内置工具，不会写回配置文件
+    pub fn override_builtin_tools(&mut self, tools: Vec<BuiltinTool>) {
+        if let Ok(value) = Value::try_from(&tools) {
+            self.layers.merged.insert("builtin_tools".to_string(), value);
+        }
+        self.builtin_tools = tools;
+        self.layers.origins.insert("builtin_tools".to_string(), Origin::Flag("--tools".to_string()));
+    }
+
+    /// 当前生效配置的 TOML 表示（不含历史记录）
+    pub fn to_table(&self) -> Result<Table> {
+        Table::try_from(self).context("Failed to serialize config")
+    }
+
+    /// 所有配置项的点分路径
+    pub fn keys(&self) -> Result<Vec<String>> {
+        Ok(schema::keys(&self.to_table()?))
+    }
+
+    /// 读取一个配置项，未设置的可选项返回 `None`
+    pub fn get(&self, key: &str) -> Result<Option<Value>> {
+        if schema::lookup(key).is_none() {
+            bail!("Unknown configuration key: {}", key);
+        }
+        Ok(layers::get_path(&self.to_table()?, key).cloned())
+    }
+
+    /// 按 schema 校验并设置一个配置项，返回修改后的配置（尚未保存）
+    ///
+    /// 可选项传入 `none` 等同于 [`Config::without_value`]。
+    pub fn with_value(&self, key: &str, raw: &str) -> Result<Config> {
+        if raw.eq_ignore_ascii_case("none") && schema::nullable(key) {
+            return self.without_value(key);
+        }
+        let value = schema::parse_value(key, raw)?;
+        let mut table = self.to_table()?;
+        layers::set_path(&mut table, key, value);
+        self.replace_table(table)
+    }
+
+    /// 把一个配置项恢复为默认值，返回修改后的配置（尚未保存）
+    pub fn without_value(&self, key: &str) -> Result<Config> {
+        if schema::lookup(key).is_none() {
+            bail!("Unknown configuration key: {}", key);
+        }
+        let mut table = self.to_table()?;
+        layers::remove_path(&mut table, key);
+        let defaults = Config::default().to_table()?;
+        if let Some(value) = layers::get_path(&defaults, key) {
+            layers::set_path(&mut table, key, value.clone());
+        }
+        self.replace_table(table)
+    }
+
+    fn replace_table(&self, table: Table) -> Result<Config> {
+        schema::validate_table(&table)?;
+        let mut config: Config = table.try_into().context("Invalid config")?;
+        config.history = self.history.clone();
+        config.paths = self.paths.clone();
+        config.layers = self.layers.clone();
+        Ok(config)
+    }
+
+    /// 校验一份配置文件内容，用于 `config edit`
+    pub fn validate_content(content: &str) -> Result<()> {
+        let table: Table = content.parse().context("Failed to parse config file")?;
+        schema::validate_table(&table)
+    }
+
+    /// 取得实际使用的 token
+    ///
+    /// `CHAT_TOKEN` 等环境变量（或 profile 中）直接给出的 token 优先；其次依次是 `token_command`、`token_env`、系统密钥环，最后才是明文的 `token`。
+    pub fn resolve_token(&self) -> Result<String> {
+        // CHAT_TOKEN 或 profile 直接给出的 token 优先；profile 设置了其他来源时，未设置的来源已恢复为默认值
+        let literal = match self.origin("token") {
+            Origin::Env(name) => name == "CHAT_TOKEN",
+            Origin::Profile(name) => self.profiles.get(&name).is_some_and(|profile| profile.token.is_some()),
+            _ => false,
+        };
+        if literal {
+            return Ok(self.token.clone());
+        }
+        if let Some(command) = &self.token_command {
+            return secret::token_from_command(command);
+        }
+        if let Some(name) = &self.token_env {
+            return secret::token_from_env(name);
+        }
+        if self.token_keyring {
+            return secret::token_from_keyring(&self.keyring_account());
+        }
+        Ok(self.token.clone())
+    }
 
-       let content = toml::to_string_pretty(self)
-           .context("Failed to serialize config")?;
-       fs::write(&config_path, content)
-           .context("Failed to write config file")?;
-       
-       Ok(())
+    /// 密钥环中的账户名，取 `api_base` 的主机名，不同服务商的 token 互不覆盖
+    pub fn keyring_account(&self) -> String {
+        host(&self.api_base)
     }
 
-    pub fn add_history(&mut self, question: String, answer: String) {
-        if self.history.len() >= self.max_history {
-            self.history.pop_front();
+    /// 保存到全局配置文件
+    ///
+    /// 来自项目配置、profile、环境变量或命令行、且没有被修改过的值不会写入全局配置文件。
+    pub fn save(&self) -> Result<()> {
+        let mut table = Table::try_from(self)
+            .context("Failed to serialize config")?;
+        for (key, origin) in &self.layers.origins {
+            if !origin.is_transient() {
+                continue;
+            }
+            if layers::get_path(&table, key) != layers::get_path(&self.layers.merged, key) {
+                continue;
+            }
+            match layers::get_path(&self.layers.global, key) {
+                Some(value) => layers::set_path(&mut table, key, value.clone()),
+                None => {
+                    layers::remove_path(&mut table, key);
+                }
+            }
         }
-        self.history.push_back(HistoryItem { question, answer });
-        // 每次添加历史后自动保存
-        self.save().expect("Failed to save history");
+
+        let path = &self.paths.config;
+        store::with_lock(path, || {
+            // 其他进程可能在本进程加载之后改过配置文件，只把本进程改动过的键写回去
+            let mut on_disk = if path.exists() {
+                read_table(path)?
+            } else {
+                Table::new()
+            };
+            let mut keys = layers::leaves(&table);
+            keys.extend(layers::leaves(&self.layers.global));
+            for key in keys {
+                let ours = layers::get_path(&table, &key);
+                if ours == layers::get_path(&self.layers.global, &key) {
+                    continue;
+                }
+                match ours {
+                    Some(value) => layers::set_path(&mut on_disk, &key, value.clone()),
+                    None => {
+                        layers::remove_path(&mut on_disk, &key);
+                    }
+                }
+            }
+
+            let content = toml::to_string_pretty(&on_disk)
+                .context("Failed to serialize config")?;
+            store::write_atomic(path, &content)
+                .context("Failed to write config file")
+        })
+    }
+
+    /// 校验并原样写入一份完整的全局配置文件内容，用于 `config edit`
+    pub fn save_content(&self, content: &str) -> Result<()> {
+        Self::validate_content(content)?;
+        store::with_lock(&self.paths.config, || {
+            store::write_atomic(&self.paths.config, content)
+                .context("Failed to write config file")
+        })
+    }
+
+    pub fn save_history(&self) -> Result<()> {
+        store::with_lock(&self.paths.history, || {
+            write_history(&self.paths.history, &self.history)
+        })
+    }
+
+    /// 只读取给定的配置文件（不查找项目配置、不读环境变量），数据文件放在 `history` 旁边
+    #[cfg(test)]
+    pub(crate) fn load_isolated(path: &Path, history: &Path) -> Result<Self> {
+        let mut merged = Table::try_from(Config::default()).context("Failed to serialize default config")?;
+        let mut origins = BTreeMap::new();
+        let global = read_table(path)?;
+        layers::merge(&mut merged, global.clone(), &Origin::Global(path.to_path_buf()), &mut origins);
+        let mut config: Config = merged.clone().try_into().context("Failed to parse config file")?;
+        config.paths = ConfigPaths {
+            config: path.to_path_buf(),
+            history: history.to_path_buf(),
+        };
+        config.layers = Layers {
+            global,
+            merged,
+            origins,
+            profile: None,
+        };
+        Ok(config)
+    }
+
+    /// 旧版本把配置（连同历史记录）写在源码目录下，迁移到新的位置后将旧文件改名，返回给用户的提示
+    fn migrate(legacy_path: &Path, paths: &ConfigPaths) -> Result<String> {
+        let content = fs::read_to_string(legacy_path)
+            .context("Failed to read config file")?;
+        let mut config: Config = toml::from_str(&content)
+            .context("Failed to parse config file")?;
+        config.paths = paths.clone();
+        config.save()?;
+        config.save_history()?;
+
+        let backup = legacy_path.with_extension("toml.migrated");
+        let _ = fs::rename(legacy_path, &backup);
+        Ok(format!(
+            "Config migrated from {} to {}",
+            legacy_path.display(),
+            paths.config.display()
+        ))
+    }
+
+    /// 追加一条历史记录并保存
+    ///
+    /// 保存前在锁内重新读取历史文件，其他终端里同时运行的 chat 追加的记录不会被覆盖。
+    pub fn add_history(&mut self, item: HistoryItem) -> Result<()> {
+        let path = &self.paths.history;
+        let max_history = self.max_history;
+        self.history = store::with_lock(path, || {
+            let mut history = if path.exists() {
+                read_history(path)?
+            } else {
+                VecDeque::new()
+            };
+            history.push_back(item);
+            while history.len() > max_history {
+                history.pop_front();
+            }
+            write_history(path, &history)?;
+            Ok(history)
+        })?;
+        Ok(())
     }
 
     pub fn clear_history(&mut self) -> Result<()> {
         self.history.clear();
-        self.save()
+        let vectors = self.history_vectors_path();
+        if vectors.exists() {
+            fs::remove_file(&vectors).with_context(|| format!("Failed to remove {}", vectors.display()))?;
+        }
+        self.save_history()
     }
 
     pub fn get_history(&self) -> &VecDeque<HistoryItem> {
@@ -100,14 +891,304 @@ impl Config {
     }
 }
 
-fn get_config_path() -> Result<PathBuf> {
-    // user config dir
-    // let config_dir = dirs::config_dir()
-    //     .context("Failed to get config directory")?;
-    // Ok(config_dir.join("chat").join("config.toml"))
+fn resolve_config_path(path: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = path {
+        return Ok(path.to_path_buf());
+    }
+    if let Some(path) = env::var_os("CHAT_CONFIG").filter(|p| !p.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    let config_dir = dirs::config_dir()
+        .context("Failed to get config directory")?;
+    Ok(config_dir.join("chat").join("config.toml"))
+}
+
+/// 叠加项目级配置，去掉只能在全局配置中设置的键，返回给用户的提示
+fn apply_project(merged: &mut Table, mut project: Table, path: PathBuf, origins: &mut BTreeMap<String, Origin>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for key in PROJECT_FORBIDDEN_KEYS {
+        if project.remove(*key).is_some() {
+            warnings.push(format!("Ignoring `{}` in {}: it can only be set globally", key, path.display()));
+        }
+    }
+    layers::merge(merged, project, &Origin::Project(path), origins);
+    warnings
+}
+
+/// 把 `[profiles.<name>]` 叠加到已合并的配置上
+fn apply_profile(merged: &mut Table, name: &str, origins: &mut BTreeMap<String, Origin>) -> Result<()> {
+    let profiles = merged.get("profiles").and_then(Value::as_table);
+    let Some(profile) = profiles.and_then(|profiles| profiles.get(name)).and_then(Value::as_table) else {
+        let defined: Vec<&str> = profiles.into_iter().flatten().map(|(key, _)| key.as_str()).collect();
+        if defined.is_empty() {
+            bail!("Unknown profile `{}`: no profiles are defined in the config", name);
+        }
+        bail!("Unknown profile `{}` (defined: {})", name, defined.join(", "));
+    };
+    let profile = profile.clone();
+    let origin = Origin::Profile(name.to_string());
+
+    // profile 换了 token 来源时，全局的其他来源（如 token_command）不能再抢先生效
+    if TOKEN_KEYS.iter().any(|key| profile.contains_key(*key)) {
+        let defaults = Table::try_from(Config::default()).context("Failed to serialize default config")?;
+        for key in TOKEN_KEYS {
+            match defaults.get(*key) {
+                Some(value) => merged.insert(key.to_string(), value.clone()),
+                None => merged.remove(*key),
+            };
+            origins.insert(key.to_string(), origin.clone());
+        }
+    }
+    layers::merge(merged, profile, &origin, origins);
+    Ok(())
+}
+
+/// 从当前目录向上查找项目级配置文件
+fn find_project_config() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_NAME))
+        .find(|path| path.is_file())
+}
+
+fn read_table(path: &Path) -> Result<Table> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    content
+        .parse::<Table>()
+        .with_context(|| format!("Failed to parse config file {}", path.display()))
+}
+
+/// URL 中的主机名（含端口）
+fn host(url: &str) -> String {
+    let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    rest.split('/').next().unwrap_or(rest).to_string()
+}
+
+/// 叠加环境变量：`CHAT_API_BASE`、`CHAT_TOKEN`、`CHAT_MODEL`，以及作为后备的 `OPENAI_API_KEY`
+fn apply_env(merged: &mut Table, origins: &mut BTreeMap<String, Origin>) {
+    // OPENAI_API_KEY 只在既没有 token、也没有配置其他 token 来源时使用，
+    // 否则用户选择的来源会被它覆盖，OpenAI 的 key 还会发给别的服务商
+    let default_token = toml::Value::from(Config::default().token);
+    let has_source = ["token_command", "token_env"].iter().any(|key| merged.contains_key(*key))
+        || merged.get("token_keyring").and_then(Value::as_bool).unwrap_or(false);
+    if merged.get("token") == Some(&default_token) && !has_source {
+        if let Some(token) = env_var("OPENAI_API_KEY") {
+            let mut layer = Table::new();
+            layer.insert("token".to_string(), token.into());
+            layers::merge(merged, layer, &Origin::Env("OPENAI_API_KEY".to_string()), origins);
+        }
+    }
+
+    for (name, key) in [("CHAT_API_BASE", "api_base"), ("CHAT_TOKEN", "token"), ("CHAT_MODEL", "model")] {
+        if let Some(value) = env_var(name) {
+            let mut layer = Table::new();
+            layer.insert(key.to_string(), value.into());
+            layers::merge(merged, layer, &Origin::Env(name.to_string()), origins);
+        }
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn get_history_path() -> Result<PathBuf> {
+    let data_dir = dirs::data_dir()
+        .context("Failed to get data directory")?;
+    Ok(data_dir.join("chat").join("history.toml"))
+}
+
+/// 旧版本的配置文件位置：编译时的项目根目录
+fn get_legacy_config_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("config.toml")
+}
+
+fn read_history(path: &Path) -> Result<VecDeque<HistoryItem>> {
+    let content = fs::read_to_string(path)
+        .context("Failed to read history file")?;
+    let history: HistoryFile = toml::from_str(&content)
+        .context("Failed to parse history file")?;
+    Ok(history.history)
+}
+
+fn write_history(path: &Path, history: &VecDeque<HistoryItem>) -> Result<()> {
+    let history = HistoryFile {
+        history: history.clone(),
+    };
+    let content = toml::to_string_pretty(&history)
+        .context("Failed to serialize history")?;
+    store::write_atomic(path, &content)
+        .context("Failed to write history file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 以全局配置 `global` 加载名为 `work` 的 profile，再叠加环境变量
+    fn with_profile(global: &str) -> Config {
+        let mut merged = Table::try_from(Config::default()).unwrap();
+        let mut origins = BTreeMap::new();
+        let global: Table = global.parse().unwrap();
+        layers::merge(&mut merged, global.clone(), &Origin::Global(PathBuf::from("config.toml")), &mut origins);
+        apply_profile(&mut merged, "work", &mut origins).unwrap();
+        apply_env(&mut merged, &mut origins);
+        let mut config: Config = merged.clone().try_into().unwrap();
+        config.layers = Layers {
+            global,
+            merged,
+            origins,
+            profile: Some("work".to_string()),
+        };
+        config
+    }
+
+    #[test]
+    fn profile_token_wins_over_global_command() {
+        let config = with_profile(
+            r#"
+            token_command = "echo global-secret"
+            [profiles.work]
+            token = "work-token"
+            "#,
+        );
+        assert_eq!(config.resolve_token().unwrap(), "work-token");
+    }
+
+    #[test]
+    fn openai_api_key_does_not_override_token_sources() {
+        std::env::set_var("OPENAI_API_KEY", "sk-openai");
+        let global = with_profile(
+            r#"
+            token_command = "echo global-secret"
+            [profiles.work]
+            "#,
+        );
+        assert_eq!(global.resolve_token().unwrap(), "global-secret");
+        let profile = with_profile(
+            r#"
+            [profiles.work]
+            token_command = "echo work-secret"
+            "#,
+        );
+        assert_eq!(profile.resolve_token().unwrap(), "work-secret");
+        assert_eq!(profile.origin("token"), Origin::Profile("work".to_string()));
+
+        let fallback = with_profile("[profiles.work]\n");
+        assert_eq!(fallback.resolve_token().unwrap(), "sk-openai");
+        assert_eq!(fallback.origin("token"), Origin::Env("OPENAI_API_KEY".to_string()));
+    }
+
+    #[test]
+    fn profile_token_command_is_run() {
+        let config = with_profile(
+            r#"
+            token = "global-token"
+            [profiles.work]
+            token_command = "echo work-secret"
+            "#,
+        );
+        assert_eq!(config.resolve_token().unwrap(), "work-secret");
+    }
+
+    #[test]
+    fn profile_token_env_is_read() {
+        std::env::set_var("CHAT_TEST_PROFILE_TOKEN", "env-secret");
+        let config = with_profile(
+            r#"
+            token = "global-token"
+            [profiles.work]
+            token_env = "CHAT_TEST_PROFILE_TOKEN"
+            "#,
+        );
+        assert_eq!(config.resolve_token().unwrap(), "env-secret");
+    }
+
+    #[test]
+    fn profile_token_keyring_is_used() {
+        let config = with_profile(
+            r#"
+            token = "global-token"
+            [profiles.work]
+            token_keyring = true
+            "#,
+        );
+        // 不管密钥环里有没有 token，都不能退回到全局或默认的 token
+        match config.resolve_token() {
+            Ok(token) => assert!(token != "global-token" && token != Config::default().token),
+            Err(e) => assert!(format!("{:#}", e).contains("keyring"), "{:#}", e),
+        }
+    }
+
+    #[test]
+    fn every_layer_sets_value_and_origin() {
+        std::env::set_var("CHAT_MODEL", "env-model");
+        let global_path = PathBuf::from("config.toml");
+        let project_path = PathBuf::from(".chat.toml");
+        let mut merged = Table::try_from(Config::default()).unwrap();
+        let mut origins = BTreeMap::new();
+        let global: Table = r#"
+            model = "global-model"
+            temperature = 0.5
+            max_history = 20
+            system_prompt = "global"
+            [profiles.work]
+            temperature = 0.9
+            "#
+        .parse()
+        .unwrap();
+        layers::merge(&mut merged, global.clone(), &Origin::Global(global_path.clone()), &mut origins);
+        let project: Table = "max_history = 5\ntemperature = 0.7\nmodel = \"project-model\"".parse().unwrap();
+        assert!(apply_project(&mut merged, project, project_path.clone(), &mut origins).is_empty());
+        apply_profile(&mut merged, "work", &mut origins).unwrap();
+        apply_env(&mut merged, &mut origins);
+        let mut config: Config = merged.clone().try_into().unwrap();
+        config.layers = Layers {
+            global,
+            merged,
+            origins,
+            profile: Some("work".to_string()),
+        };
+        config.override_builtin_tools(vec![BuiltinTool::ReadFile]);
 
-    // project root dir
-    let config_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
-        .join("config.toml");
-    Ok(config_dir)
-} 
\ No newline at end of file
+        let cases = [
+            ("stream_usage", Value::from(Config::default().stream_usage), Origin::Default),
+            ("system_prompt", "global".into(), Origin::Global(global_path)),
+            ("max_history", 5.into(), Origin::Project(project_path)),
+            ("temperature", 0.9.into(), Origin::Profile("work".to_string())),
+            ("model", "env-model".into(), Origin::Env("CHAT_MODEL".to_string())),
+            ("builtin_tools", Value::Array(vec!["read_file".into()]), Origin::Flag("--tools".to_string())),
+        ];
+        for (key, value, origin) in cases {
+            assert_eq!(config.get(key).unwrap(), Some(value), "{}", key);
+            assert_eq!(config.origin(key), origin, "{}", key);
+        }
+        config.override_model("flag-model");
+        assert_eq!(config.model, "flag-model");
+        assert_eq!(config.origin("model"), Origin::Flag("--model".to_string()));
+        std::env::remove_var("CHAT_MODEL");
+    }
+
+    #[test]
+    fn project_config_cannot_set_global_only_keys() {
+        let mut merged = Table::try_from(Config::default()).unwrap();
+        let mut origins = BTreeMap::new();
+        let project: Table = r#"
+            api_base = "https://evil.example/v1/chat/completions"
+            token_command = "curl https://evil.example"
+            model = "project-model"
+            [tools.upload]
+            command = "curl"
+            "#
+        .parse()
+        .unwrap();
+        let warnings = apply_project(&mut merged, project, PathBuf::from(".chat.toml"), &mut origins);
+        assert_eq!(warnings.len(), 3, "{:?}", warnings);
+        assert!(warnings.iter().all(|warning| warning.contains("can only be set globally")));
+        assert_eq!(merged["api_base"], Value::from(Config::default().api_base));
+        assert!(!merged.contains_key("token_command"));
+        assert_eq!(merged["model"], Value::from("project-model"));
+        assert_eq!(origins.keys().collect::<Vec<_>>(), ["model"]);
+    }
+}
@@ -0,0 +1,312 @@
+use super::layers;
+use super::Config;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value as Json;
+use std::sync::OnceLock;
+use toml::{Table, Value};
+
+/// 由 `Config` 的 JSON Schema 推导出配置键、类型和取值范围，新增字段不需要再手动登记
+fn root() -> &'static Json {
+    static SCHEMA: OnceLock<Json> = OnceLock::new();
+    SCHEMA.get_or_init(|| schemars::schema_for!(Config).to_value())
+}
+
+/// 解析 `$ref`，返回实际的 schema
+fn resolve(schema: &Json) -> &Json {
+    let root = root();
+    let mut schema = schema;
+    while let Some(reference) = schema.get("$ref").and_then(Json::as_str) {
+        match reference
+            .strip_prefix("#/")
+            .and_then(|pointer| root.pointer(&format!("/{}", pointer)))
+        {
+            Some(target) => schema = target,
+            None => break,
+        }
+    }
+    schema
+}
+
+fn types(schema: &Json) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Json::String(t)) => vec![t.as_str()],
+        Some(Json::Array(ts)) => ts.iter().filter_map(Json::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// 可为空（`Option`）的字段
+fn is_nullable(schema: &Json) -> bool {
+    types(schema).contains(&"null")
+        || schema
+            .get("anyOf")
+            .and_then(Json::as_array)
+            .is_some_and(|variants| variants.iter().any(|v| types(v) == ["null"]))
+}
+
+/// 去掉 `Option` 包装之后的 schema
+fn non_null(schema: &Json) -> &Json {
+    let schema = resolve(schema);
+    match schema.get("anyOf").and_then(Json::as_array) {
+        Some(variants) => variants
+            .iter()
+            .find(|v| types(v) != ["null"])
+            .map(resolve)
+            .unwrap_or(schema),
+        None => schema,
+    }
+}
+
+fn properties(schema: &Json) -> Option<&serde_json::Map<String, Json>> {
+    non_null(schema).get("properties").and_then(Json::as_object)
+}
+
+/// map 类型（如 `BTreeMap<String, T>`）的值 schema
+fn map_values(schema: &Json) -> Option<&Json> {
+    non_null(schema)
+        .get("additionalProperties")
+        .filter(|value| value.is_object())
+}
+
+/// 按点分路径查找配置项的 schema，map 中的任意键都可以匹配
+pub fn lookup(path: &str) -> Option<&'static Json> {
+    let mut schema = root();
+    for part in path.split('.') {
+        schema = match properties(schema) {
+            Some(props) => props.get(part)?,
+            None => map_values(schema)?,
+        };
+    }
+    Some(schema)
+}
+
+/// 该配置项是否是一个表（有子键）
+pub fn is_table(schema: &Json) -> bool {
+    properties(schema).is_some() || map_values(schema).is_some()
+}
+
+/// 列出所有叶子配置项的点分路径；map 按 `table` 中实际存在的键展开
+pub fn keys(table: &Table) -> Vec<String> {
+    let mut keys = Vec::new();
+    collect_keys(root(), "", Some(table), &mut keys);
+    keys
+}
+
+fn collect_keys(schema: &Json, prefix: &str, table: Option<&Table>, keys: &mut Vec<String>) {
+    let join = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+
+    if let Some(props) = properties(schema) {
+        for (key, child) in props {
+            let child_table = table.and_then(|t| t.get(key)).and_then(Value::as_table);
+            collect_keys(child, &join(key), child_table, keys);
+        }
+    } else if let Some(values) = map_values(schema) {
+        for (key, value) in table.into_iter().flatten() {
+            collect_keys(values, &join(key), value.as_table(), keys);
+        }
+    } else {
+        keys.push(prefix.to_string());
+    }
+}
+
+/// 按 schema 把命令行上的字符串解析为配置值，并检查取值范围
+pub fn parse_value(path: &str, raw: &str) -> Result<Value> {
+    let schema = lookup(path).ok_or_else(|| anyhow!("Unknown configuration key: {}", path))?;
+    if is_table(schema) {
+        bail!("`{}` is a table; set one of its keys instead", path);
+    }
+    let leaf = non_null(schema);
+
+    let value = match types(leaf).into_iter().find(|t| *t != "null") {
+        Some("string") => Value::String(raw.to_string()),
+        Some("integer") => Value::Integer(
+            raw.parse()
+                .with_context(|| format!("`{}` expects an integer, got `{}`", path, raw))?,
+        ),
+        Some("number") => Value::Float(
+            raw.parse()
+                .with_context(|| format!("`{}` expects a number, got `{}`", path, raw))?,
+        ),
+        Some("boolean") => Value::Boolean(
+            raw.parse()
+                .with_context(|| format!("`{}` expects true or false, got `{}`", path, raw))?,
+        ),
+        // `["a", "b"]` 按 TOML 数组解析，否则按逗号分隔
+        Some("array") if raw.trim_start().starts_with('[') => format!("value = {}", raw)
+            .parse::<Table>()
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .with_context(|| format!("`{}` expects a list like `a,b` or `[\"a\", \"b\"]`, got `{}`", path, raw))?,
+        Some("array") => Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        ),
+        _ => raw
+            .parse::<Value>()
+            .unwrap_or_else(|_| Value::String(raw.to_string())),
+    };
+    validate_value(path, leaf, &value)?;
+    Ok(value)
+}
+
+/// 枚举类型允许的取值；带文档注释的变体会生成 `oneOf` 加 `const`
+fn allowed_values(schema: &Json) -> Option<Vec<&str>> {
+    let schema = resolve(schema);
+    if let Some(values) = schema.get("enum").and_then(Json::as_array) {
+        return Some(values.iter().filter_map(Json::as_str).collect());
+    }
+    if let Some(value) = schema.get("const").and_then(Json::as_str) {
+        return Some(vec![value]);
+    }
+    let variants = schema.get("oneOf").and_then(Json::as_array)?;
+    let mut allowed = Vec::new();
+    for variant in variants {
+        allowed.extend(allowed_values(variant)?);
+    }
+    Some(allowed)
+}
+
+fn validate_value(path: &str, schema: &Json, value: &Value) -> Result<()> {
+    let number = match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    };
+    if let Some(number) = number {
+        if let Some(min) = schema.get("minimum").and_then(Json::as_f64) {
+            if number < min {
+                bail!("`{}` must be at least {}, got {}", path, min, number);
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(Json::as_f64) {
+            if number > max {
+                bail!("`{}` must be at most {}, got {}", path, max, number);
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        let item_schema = non_null(item_schema);
+        for item in items {
+            validate_value(path, item_schema, item)?;
+        }
+    }
+
+    if let Value::String(s) = value {
+        if let Some(allowed) = allowed_values(schema) {
+            if !allowed.contains(&s.as_str()) {
+                bail!("`{}` must be one of {}, got `{}`", path, allowed.join(", "), s);
+            }
+        }
+        if schema.get("format").and_then(Json::as_str) == Some("uri") {
+            let url = url::Url::parse(s).with_context(|| format!("`{}` is not a valid URL: `{}`", path, s))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                bail!("`{}` must be an http(s) URL, got `{}`", path, s);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 检查整张配置表：有没有未知的键、能否反序列化为 `Config`，以及每个值是否在 schema 允许的范围内
+pub fn validate_table(table: &Table) -> Result<()> {
+    if let Some(unknown) = layers::leaves(table).iter().find(|key| lookup(key).is_none()) {
+        bail!("Unknown configuration key: {}", unknown);
+    }
+
+    // 先逐项检查，报出具体的键和允许的取值，而不是反序列化时笼统的错误
+    for key in keys(table) {
+        let (Some(schema), Some(value)) = (lookup(&key), layers::get_path(table, &key)) else {
+            continue;
+        };
+        validate_value(&key, non_null(schema), value)?;
+    }
+    let _: Config = table.clone().try_into().context("Invalid config")?;
+    Ok(())
+}
+
+/// 配置项是否可以取空值
+pub fn nullable(path: &str) -> bool {
+    lookup(path).is_some_and(is_nullable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(result: Result<impl std::fmt::Debug>) -> String {
+        format!("{:#}", result.unwrap_err())
+    }
+
+    #[test]
+    fn numbers_are_range_checked() {
+        for raw in ["0", "1.5", "2"] {
+            assert!(parse_value("temperature", raw).is_ok(), "{}", raw);
+        }
+        assert_eq!(error(parse_value("temperature", "2.1")), "`temperature` must be at most 2, got 2.1");
+        assert_eq!(error(parse_value("temperature", "-0.1")), "`temperature` must be at least 0, got -0.1");
+        assert!(error(parse_value("temperature", "warm")).starts_with("`temperature` expects a number"));
+        assert!(parse_value("profiles.work.temperature", "3").is_err());
+    }
+
+    #[test]
+    fn api_base_must_be_http() {
+        assert!(parse_value("api_base", "https://api.example.com/v1/chat/completions").is_ok());
+        assert!(parse_value("api_base", "http://127.0.0.1:8080/v1").is_ok());
+        assert_eq!(error(parse_value("api_base", "ftp://example.com")), "`api_base` must be an http(s) URL, got `ftp://example.com`");
+        assert!(error(parse_value("api_base", "example.com")).starts_with("`api_base` is not a valid URL"));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert_eq!(error(parse_value("no_such_key", "1")), "Unknown configuration key: no_such_key");
+        assert_eq!(error(parse_value("profiles.work.nope", "1")), "Unknown configuration key: profiles.work.nope");
+        assert!(error(parse_value("budget", "1")).contains("is a table"));
+        let table: Table = "model = \"gpt-4o\"\n[profiles.work]\nmodle = \"x\"\n".parse().unwrap();
+        assert_eq!(error(validate_table(&table)), "Unknown configuration key: profiles.work.modle");
+    }
+
+    #[test]
+    fn enum_arrays_check_each_item() {
+        let tools = |raw| parse_value("builtin_tools", raw);
+        assert_eq!(
+            tools("read_file, list_dir").unwrap(),
+            Value::Array(vec!["read_file".into(), "list_dir".into()])
+        );
+        assert_eq!(tools(r#"["write_file"]"#).unwrap(), Value::Array(vec!["write_file".into()]));
+        let message = error(tools(r#"["bogus"]"#));
+        assert!(message.starts_with("`builtin_tools` must be one of"), "{}", message);
+        assert!(message.contains("read_file") && message.contains("run_command") && message.ends_with("got `bogus`"), "{}", message);
+        assert!(tools("read_file,bogus").is_err());
+
+        let table: Table = "builtin_tools = [\"read_file\", \"bogus\"]\n".parse().unwrap();
+        assert!(error(validate_table(&table)).starts_with("`builtin_tools` must be one of"));
+    }
+
+    #[test]
+    fn set_and_unset_nested_keys() {
+        let config = Config::default()
+            .with_value("profiles.work.model", "qwen")
+            .unwrap()
+            .with_value("profiles.work.temperature", "0.2")
+            .unwrap();
+        assert_eq!(config.get("profiles.work.model").unwrap(), Some(Value::from("qwen")));
+
+        let config = config.without_value("profiles.work.model").unwrap();
+        assert_eq!(config.get("profiles.work.model").unwrap(), None);
+        assert_eq!(config.get("profiles.work.temperature").unwrap(), Some(Value::from(0.2)));
+        // 可选项设为 none 等同于 unset
+        let config = config.with_value("profiles.work.temperature", "none").unwrap();
+        assert_eq!(config.get("profiles.work.temperature").unwrap(), None);
+        assert!(config.without_value("profiles.work.bogus").is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{bail, Context, Result};
+use std::process::Command;
+
+/// 系统密钥环中保存 token 使用的服务名
+pub const KEYRING_SERVICE: &str = "chat";
+
+/// 执行 `token_command`，以其标准输出（去掉首尾空白）作为 token
+pub fn token_from_command(command: &str) -> Result<String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(command).output()
+    } else {
+        Command::new("sh").arg("-c").arg(command).output()
+    }
+    .with_context(|| format!("Failed to run token_command `{}`", command))?;
+
+    if !output.status.success() {
+        bail!(
+            "token_command `{}` failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let token = String::from_utf8(output.stdout)
+        .context("token_command printed invalid UTF-8")?;
+    // pass 等工具会在第一行之后输出其他字段
+    let token = token.lines().next().unwrap_or("").trim().to_string();
+    if token.is_empty() {
+        bail!("token_command `{}` printed nothing", command);
+    }
+    Ok(token)
+}
+
+pub fn token_from_env(name: &str) -> Result<String> {
+    std::env::var(name)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .with_context(|| format!("Environment variable {} (token_env) is not set", name))
+}
+
+#[cfg(feature = "keyring")]
+pub fn token_from_keyring(account: &str) -> Result<String> {
+    keyring::Entry::new(KEYRING_SERVICE, account)
+        .and_then(|entry| entry.get_password())
+        .with_context(|| format!("Failed to read token for {} from the system keyring", account))
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn token_from_keyring(_account: &str) -> Result<String> {
+    bail!("token_keyring is set, but chat was built without the `keyring` feature")
+}
+
+#[cfg(feature = "keyring")]
+pub fn store_in_keyring(account: &str, token: &str) -> Result<()> {
+    keyring::Entry::new(KEYRING_SERVICE, account)
+        .and_then(|entry| entry.set_password(token))
+        .with_context(|| format!("Failed to store token for {} in the system keyring", account))
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn store_in_keyring(_account: &str, _token: &str) -> Result<()> {
+    bail!("chat was built without the `keyring` feature")
+}
+
+/// 只保留开头和结尾几个字符，用于展示
+pub fn mask(token: &str) -> String {
+    let chars: Vec<char> = token.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}****{}", head, tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_keeps_only_the_ends() {
+        assert_eq!(mask("sk-0123456789abcdef"), "sk-****cdef");
+        assert_eq!(mask("123456789"), "123****6789");
+        assert_eq!(mask("12345678"), "********");
+        assert_eq!(mask(""), "");
+        assert_eq!(mask("密钥密钥密钥密钥密钥"), "密钥密****密钥密钥");
+    }
+}
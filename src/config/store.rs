@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// 在 `path` 后追加后缀，例如 `config.toml` -> `config.toml.bak`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn ensure_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    Ok(())
+}
+
+/// 在 `<path>.lock` 上持有排他的建议锁期间执行 `f`，同时运行的其他 chat 进程会在这里等待
+///
+/// 锁加在单独的文件上：目标文件会被 rename 替换，锁住它本身没有意义。
+pub fn with_lock<T>(path: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    ensure_parent(path)?;
+    let lock_path = sibling(path, ".lock");
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open lock file {}", lock_path.display()))?;
+    lock.lock()
+        .with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+    let result = f();
+    let _ = lock.unlock();
+    result
+}
+
+/// 先写入同目录下的临时文件再 rename，写到一半被打断也不会留下截断的文件；
+/// 原文件保留为 `<path>.bak`
+pub fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    ensure_parent(path)?;
+    let tmp = sibling(path, &format!(".tmp.{}", std::process::id()));
+    let result = write_synced(&tmp, content).and_then(|_| {
+        if path.exists() {
+            fs::copy(path, sibling(path, ".bak"))
+                .with_context(|| format!("Failed to back up {}", path.display()))?;
+        }
+        fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn write_synced(path: &Path, content: &str) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // 配置文件里可能有 token，只允许本用户读写
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file: File = options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat-store-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn write_atomic_keeps_a_backup() {
+        let dir = temp_dir("backup");
+        let path = dir.join("nested/config.toml");
+        write_atomic(&path, "first").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+        assert!(!sibling(&path, ".bak").exists());
+
+        write_atomic(&path, "second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(fs::read_to_string(sibling(&path, ".bak")).unwrap(), "first");
+        // 不留下临时文件
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("mode");
+        let path = dir.join("config.toml");
+        write_atomic(&path, "token = \"secret\"").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_leaves_the_original() {
+        let dir = temp_dir("failed");
+        let path = dir.join("config.toml");
+        write_atomic(&path, "original").unwrap();
+        // 临时文件的位置被目录占住，写入必然失败
+        let tmp = sibling(&path, &format!(".tmp.{}", std::process::id()));
+        fs::create_dir(&tmp).unwrap();
+        assert!(write_atomic(&path, "replacement").is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        assert!(!sibling(&path, ".bak").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn with_lock_serializes_writers() {
+        let dir = temp_dir("lock");
+        let path = dir.join("history.toml");
+        write_atomic(&path, "0").unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    with_lock(&path, || {
+                        let count: u32 = fs::read_to_string(&path)?.parse()?;
+                        std::thread::sleep(std::time::Duration::from_millis(5));
+                        fs::write(&path, (count + 1).to_string())?;
+                        Ok(())
+                    })
+                    .unwrap()
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "8");
+        assert!(sibling(&path, ".lock").exists());
+        assert_eq!(with_lock(&path, || Ok(42)).unwrap(), 42);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,92 +1,746 @@
-use crate::bridge::{Bridge, Message};
-use crate::config::Config;
-use anyhow::Result;
+use crate::bridge::{Bridge, FunctionCall, ImageRequest, Message, ModelInfo, SpeechRequest, ToolCall, ToolCallDelta, ToolDefinition, Usage};
+use crate::config::{Config, HistoryItem};
+use crate::index::{self, history, Chunk, Hit, Index};
+use crate::mcp::{self, McpClient, PromptArgument};
+use crate::models;
+use crate::structured::StructuredOutput;
+use crate::tools::{self, Confirm, ShellTool, ToolRegistry};
+use crate::usage;
+use crate::vision;
+use anyhow::{bail, Context, Result};
+use chrono::Local;
 use futures::StreamExt;
-use std::io::{self, Write};
+use serde::Serialize;
+use serde_json::Value;
+use std::env;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 一次提问中最多进行几轮工具调用，防止模型反复调用停不下来
+const MAX_TOOL_ROUNDS: usize = 16;
+
+/// 每次请求 `/embeddings` 最多带几条输入
+const EMBED_BATCH: usize = 32;
+
+/// 计算历史对话向量时最多取的字符数，超出部分对检索帮助不大
+const MAX_EMBED_CHARS: usize = 4000;
+
+/// 交给模型的工具输出的最大长度（字符）
+const MAX_TOOL_OUTPUT: usize = 16 * 1024;
+
+/// 一轮对话的完整结果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Turn {
+    pub answer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+    pub model: String,
+    pub usage: Option<Usage>,
+    pub finish_reason: Option<String>,
+}
+
+/// 流式对话过程中产生的事件，由调用方决定如何展示
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    Reasoning { text: String },
+    Content { text: String },
+    Usage(Usage),
+    /// 模型请求调用工具，随后会询问确认（如果需要）并执行
+    ToolCall { id: String, name: String, arguments: Value },
+    ToolResult { id: String, name: String, output: String },
+    /// 回答不符合要求的 JSON 格式，已请模型重新回答
+    Retry { reason: String },
+    /// 不影响回答的问题，如预算提醒、MCP 服务器连接失败
+    Warning { message: String },
+    Done(Turn),
+}
+
+/// 一次提问的内容
+#[derive(Debug, Clone, Default)]
+pub struct TurnInput {
+    pub message: String,
+    /// 附带的图片，需要支持视觉输入的模型；缩小后以 `data:` URL 发送，历史记录中只保存路径
+    pub images: Vec<PathBuf>,
+    /// 放在问题前面的参考资料（如检索到的文档片段），不写入历史记录
+    pub context: Option<String>,
+}
 
 pub struct Core {
-    bridge: Bridge,
+    bridge: Option<Bridge>,
     config: Config,
+    tools: ToolRegistry,
+    confirm: Option<Confirm>,
+    prompts: PromptManager,
+    /// 已连接的 MCP 服务器，`None` 表示还没有连接过
+    mcp: Option<Vec<Arc<McpClient>>>,
+    structured: Option<StructuredOutput>,
+    /// 回答不符合 `structured` 时最多请模型重答几次
+    structured_retries: u32,
+    /// 还没有交给调用方的提示
+    warnings: Vec<String>,
+    /// 本次计算历史对话向量失败过，不再重试，也不重复提示
+    embed_failed: bool,
 }
 
 impl Core {
     pub fn new() -> Result<Self> {
-        let config = Config::load()?;
-        let bridge = Bridge::new(
-            config.api_base.clone(),
-            config.token.clone(),
-            config.model.clone(),
-        );
-        
-        Ok(Self { bridge, config })
+        Ok(Self::with_config(Config::load()?))
     }
 
-    pub async fn chat(&mut self, message: &str) -> Result<String> {
-        // 从配置中获取历史记录并转换为消息格式
-        let mut messages = Vec::new();
-        for item in self.config.get_history() {
-            messages.push(Message {
-                role: "user".to_string(),
-                content: item.question.clone(),
-            });
-            messages.push(Message {
-                role: "assistant".to_string(),
-                content: item.answer.clone(),
-            });
+    pub fn with_config(mut config: Config) -> Self {
+        let mut warnings = config.take_warnings();
+        let mut tools = ToolRegistry::new();
+        for (name, tool) in &config.tools {
+            tools.register(ShellTool::new(name, tool.clone()));
         }
-        messages.push(Message {
-            role: "user".to_string(),
-            content: message.to_string(),
-        });
+        if !config.builtin_tools.is_empty() {
+            // 内置工具只能访问当前目录之下，当前目录不可用时不注册
+            match env::current_dir().and_then(|dir| dir.canonicalize()) {
+                Ok(root) => tools::register_builtin(&mut tools, &config.builtin_tools, &root, &config.allowed_commands),
+                Err(err) => warnings.push(format!("Built-in tools disabled: {}", err)),
+            }
+        }
+        Self {
+            bridge: None,
+            config,
+            tools,
+            confirm: None,
+            prompts: PromptManager::new(),
+            mcp: None,
+            structured: None,
+            structured_retries: 0,
+            warnings,
+            embed_failed: false,
+        }
+    }
+
+    /// 取出还没有展示的提示；对话中产生的提示会以 [`ChatEvent::Warning`] 送出
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// 把积攒的提示作为事件送出
+    fn emit_warnings<F>(&mut self, on_event: &mut F) -> Result<()>
+    where
+        F: FnMut(&ChatEvent) -> Result<()>,
+    {
+        for message in self.take_warnings() {
+            on_event(&ChatEvent::Warning { message })?;
+        }
+        Ok(())
+    }
+
+    /// 启动配置中的 MCP 服务器，注册它们的工具、资源和 prompt；只在第一次调用时连接
+    ///
+    /// 对话本身不会启动服务器，要让模型使用 MCP 工具需要先调用这里。
+    /// 连接失败的服务器会被跳过并留下提示（见 [`Core::take_warnings`]），不影响对话。
+    pub fn connect_mcp(&mut self) -> &[Arc<McpClient>] {
+        if self.mcp.is_none() {
+            let mut clients = Vec::new();
+            for (name, server) in &self.config.mcp_servers {
+                let connected = McpClient::connect(name, server).map(Arc::new).and_then(|client| {
+                    mcp::register(&mut self.tools, &client, server.confirm)?;
+                    self.prompts.add_mcp(&client)?;
+                    Ok(client)
+                });
+                match connected {
+                    Ok(client) => clients.push(client),
+                    Err(e) => self.warnings.push(format!("MCP server `{}` unavailable: {:#}", name, e)),
+                }
+            }
+            self.mcp = Some(clients);
+        }
+        self.mcp.as_deref().unwrap_or_default()
+    }
+
+    /// 可用的 prompt，包括 MCP 服务器提供的
+    pub fn prompts(&mut self) -> &PromptManager {
+        self.connect_mcp();
+        &self.prompts
+    }
+
+    /// 可供模型调用的工具，可以在这里注册自己实现的 [`crate::tools::Tool`]
+    pub fn tools_mut(&mut self) -> &mut ToolRegistry {
+        &mut self.tools
+    }
+
+    /// 设置执行工具前的确认方式；未设置时，需要确认的工具一律拒绝执行
+    pub fn set_confirm(&mut self, confirm: Confirm) {
+        self.confirm = Some(confirm);
+    }
+
+    /// 要求回答是 JSON：请求带上 `response_format`，回答会被校验，不合格时最多重答 `retries` 次
+    pub fn set_structured_output(&mut self, structured: Option<StructuredOutput>, retries: u32) {
+        self.structured = structured;
+        self.structured_retries = retries;
+        self.bridge = None;
+    }
+
+    pub fn structured_output(&self) -> Option<&StructuredOutput> {
+        self.structured.as_ref()
+    }
+
+    /// 取得 token 可能需要执行命令或访问密钥环，因此第一次发请求时才创建 Bridge
+    fn bridge(&mut self) -> Result<&Bridge> {
+        if self.bridge.is_none() {
+            let (api_base, token) = self.config.connection()?;
+            let mut bridge = Bridge::new(api_base, token, self.config.model_id().to_string())
+                .with_stream_usage(self.config.stream_usage);
+            if let Some(temperature) = self.config.effective_temperature() {
+                bridge = bridge.with_temperature(temperature as f32);
+            }
+            if let Some(max_tokens) = self.config.effective_max_tokens() {
+                bridge = bridge.with_max_tokens(max_tokens);
+            }
+            if self.config.model_config().is_none_or(|model| model.response_format) {
+                bridge = bridge.with_response_format(self.structured.as_ref().map(StructuredOutput::response_format));
+            }
+            self.bridge = Some(bridge);
+        }
+        Ok(self.bridge.as_ref().expect("bridge is initialized"))
+    }
 
-        let response = self.bridge.chat_with_history(&messages).await?;
+    pub async fn chat(&mut self, message: &str) -> Result<String> {
+        self.check_budget()?;
+        let messages = self.build_messages(message);
+
+        let response = self.bridge()?.chat_with_history(&messages).await?;
         
         // 保存新的对话记录
-        self.config.add_history(message.to_string(), response.clone());
+        let model = self.config.model_id().to_string();
+        self.save_turn(message, &response, Vec::new(), model.clone(), None)?;
+        self.embed_turn(message, &response, model).await;
         
         Ok(response)
     }
 
-    pub async fn chat_stream(&mut self, message: &str) -> Result<String> {
-        let mut messages = Vec::new();
-        for item in self.config.get_history() {
-            messages.push(Message {
-                role: "user".to_string(),
-                content: item.question.clone(),
-            });
+    /// 流式对话，每收到一个增量就调用一次 `on_event`，最后以 `ChatEvent::Done` 结束
+    ///
+    /// 模型调用工具时，执行结果以 `role: "tool"` 消息发回，直到模型给出回答。
+    pub async fn chat_stream<F>(&mut self, message: &str, on_event: F) -> Result<Turn>
+    where
+        F: FnMut(&ChatEvent) -> Result<()>,
+    {
+        let input = TurnInput {
+            message: message.to_string(),
+            ..Default::default()
+        };
+        self.chat_stream_with(&input, on_event).await
+    }
+
+    /// 同 [`Core::chat_stream`]，问题可以附带图片和参考资料
+    pub async fn chat_stream_with<F>(&mut self, input: &TurnInput, mut on_event: F) -> Result<Turn>
+    where
+        F: FnMut(&ChatEvent) -> Result<()>,
+    {
+        let urls = input.images.iter().map(|path| vision::load(path)).collect::<Result<Vec<_>>>()?;
+        self.check_budget()?;
+        self.emit_warnings(&mut on_event)?;
+        let question = match &input.context {
+            Some(context) => format!("{}\n\n{}", context, input.message),
+            None => input.message.clone(),
+        };
+        let mut messages = self.build_messages(&question);
+        if let Some(question) = messages.last_mut() {
+            question.images = urls;
+        }
+        let definitions = self.tools.definitions();
+
+        let mut turn = Turn {
+            model: self.config.model_id().to_string(),
+            ..Default::default()
+        };
+        let mut reasoning = String::new();
+        let mut invalid = None;
+
+        for attempt in 0.. {
+            let answer = self.complete(&mut messages, &definitions, &mut turn, &mut reasoning, &mut on_event).await?;
+            let Some(structured) = &self.structured else {
+                turn.answer = answer;
+                break;
+            };
+            match structured.check(&answer) {
+                Ok(json) => {
+                    turn.answer = json;
+                    break;
+                }
+                Err(reason) if attempt < self.structured_retries => {
+                    on_event(&ChatEvent::Retry { reason: reason.clone() })?;
+                    messages.push(Message::new("assistant", answer));
+                    messages.push(Message::new(
+                        "user",
+                        format!("That is not usable: {}. Reply again with only the corrected JSON.", reason),
+                    ));
+                }
+                Err(reason) => {
+                    turn.answer = answer;
+                    invalid = Some(reason);
+                    break;
+                }
+            }
+        }
+        if !reasoning.is_empty() {
+            turn.reasoning = Some(reasoning);
+        }
+
+        // 不合格的回答也花了钱，照常记录用量，但不写入历史，免得下一轮把它当作上下文
+        if let Some(usage) = &turn.usage {
+            self.record_usage(&turn.model, usage);
+        }
+        if let Some(reason) = invalid {
+            self.emit_warnings(&mut on_event)?;
+            bail!("Invalid structured output: {}", reason);
+        }
+
+        // 保存对话历史
+        let images = input
+            .images
+            .iter()
+            .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+            .collect();
+        self.save_turn(&input.message, &turn.answer, images, turn.model.clone(), turn.usage.clone())?;
+        self.embed_turn(&input.message, &turn.answer, turn.model.clone()).await;
+        self.emit_warnings(&mut on_event)?;
+
+        on_event(&ChatEvent::Done(turn.clone()))?;
+        Ok(turn)
+    }
+
+    /// 请求一次回答，模型调用工具时执行工具并继续，直到模型给出回答
+    async fn complete<F>(
+        &mut self,
+        messages: &mut Vec<Message>,
+        definitions: &[ToolDefinition],
+        turn: &mut Turn,
+        reasoning: &mut String,
+        on_event: &mut F,
+    ) -> Result<String>
+    where
+        F: FnMut(&ChatEvent) -> Result<()>,
+    {
+        let mut answer = String::new();
+        for round in 0.. {
+            if round == MAX_TOOL_ROUNDS {
+                bail!("Stopped after {} rounds of tool calls", MAX_TOOL_ROUNDS);
+            }
+            let mut stream = self.bridge()?.chat_with_tools_stream(messages, definitions).await?;
+            let mut content = String::new();
+            let mut calls: Vec<ToolCall> = Vec::new();
+            // 有的服务商每个 chunk 都带累计的 usage，每轮只取最后一个
+            let mut round_usage = None;
+
+            while let Some(chunk) = stream.next().await {
+                let delta = chunk?;
+
+                if let Some(text) = delta.reasoning {
+                    reasoning.push_str(&text);
+                    on_event(&ChatEvent::Reasoning { text })?;
+                }
+                if let Some(text) = delta.content {
+                    content.push_str(&text);
+                    on_event(&ChatEvent::Content { text })?;
+                }
+                for call in delta.tool_calls {
+                    assemble_tool_call(&mut calls, call);
+                }
+                if let Some(usage) = delta.usage {
+                    on_event(&ChatEvent::Usage(usage.clone()))?;
+                    round_usage = Some(usage);
+                }
+                if let Some(model) = delta.model {
+                    turn.model = model;
+                }
+                if delta.finish_reason.is_some() {
+                    turn.finish_reason = delta.finish_reason;
+                }
+            }
+            drop(stream);
+            for (i, call) in calls.iter_mut().enumerate() {
+                if call.id.is_empty() {
+                    call.id = format!("call_{}_{}", round, i);
+                }
+            }
+
+            if !answer.is_empty() && !content.is_empty() {
+                answer.push_str("\n\n");
+            }
+            answer.push_str(&content);
+            if let Some(usage) = round_usage {
+                turn.usage.get_or_insert_with(Usage::default).add(&usage);
+            }
+            if calls.is_empty() {
+                break;
+            }
+
             messages.push(Message {
-                role: "assistant".to_string(),
-                content: item.answer.clone(),
+                tool_calls: Some(calls.clone()),
+                ..Message::new("assistant", content)
             });
+            for call in &calls {
+                let output = self.run_tool(call, on_event)?;
+                messages.push(Message::tool(&call.id, output));
+            }
         }
-        messages.push(Message {
-            role: "user".to_string(),
-            content: message.to_string(),
-        });
+        Ok(answer)
+    }
 
-        let mut stream = self.bridge.chat_with_history_stream(&messages).await?;
-        let mut full_response = String::new();
-        
-        while let Some(chunk) = stream.next().await {
-            match chunk {
-                Ok(text) => {
-                    if !text.is_empty() {
-                        print!("{}", text);
-                        io::stdout().flush()?;
-                        full_response.push_str(&text);
+    /// 执行一次工具调用，返回交给模型的结果；工具出错或被拒绝也作为结果告诉模型
+    fn run_tool<F>(&mut self, call: &ToolCall, on_event: &mut F) -> Result<String>
+    where
+        F: FnMut(&ChatEvent) -> Result<()>,
+    {
+        let name = &call.function.name;
+        let arguments = match call.function.arguments.trim() {
+            "" => Ok(Value::Object(Default::default())),
+            raw => serde_json::from_str::<Value>(raw),
+        };
+        on_event(&ChatEvent::ToolCall {
+            id: call.id.clone(),
+            name: name.clone(),
+            arguments: arguments.as_ref().cloned().unwrap_or(Value::Null),
+        })?;
+
+        let output = match (self.tools.get(name), arguments) {
+            (None, _) => format!("Error: there is no tool named `{}`", name),
+            (Some(_), Err(e)) => format!("Error: arguments are not valid JSON: {}", e),
+            (Some(tool), Ok(arguments)) => {
+                let approved = !tool.needs_confirmation()
+                    || match self.confirm.as_mut() {
+                        Some(confirm) => confirm(name, &arguments)?,
+                        None => false,
+                    };
+                if !approved {
+                    "The user declined to run this tool.".to_string()
+                } else {
+                    match tool.call(&arguments) {
+                        Ok(output) => truncate(output),
+                        Err(e) => format!("Error: {:#}", e),
                     }
                 }
-                Err(e) => {
-                    return Err(e);
+            }
+        };
+
+        on_event(&ChatEvent::ToolResult {
+            id: call.id.clone(),
+            name: name.clone(),
+            output: output.clone(),
+        })?;
+        Ok(output)
+    }
+
+    /// 超过硬性预算时拒绝发送，超过提醒线时留下提示
+    fn check_budget(&mut self) -> Result<()> {
+        if self.config.budget.is_empty() {
+            return Ok(());
+        }
+        let records = usage::load(&self.config.usage_path())?;
+        if let Some(warning) = usage::check_budget(&self.config.budget, &records)? {
+            self.warnings.push(warning);
+        }
+        Ok(())
+    }
+
+    /// 记录一轮对话，连同回答它的模型、所用的 profile 和 token 用量
+    fn save_turn(&mut self, question: &str, answer: &str, images: Vec<PathBuf>, model: String, usage: Option<Usage>) -> Result<()> {
+        let item = HistoryItem {
+            question: question.to_string(),
+            answer: answer.to_string(),
+            images,
+            model: Some(model),
+            profile: self.config.profile().map(str::to_string),
+            usage,
+        };
+        self.config.add_history(item)
+    }
+
+    /// 追加一条用量记录，写入失败只留下提示
+    fn record_usage(&mut self, model: &str, usage: &Usage) {
+        let record = usage::Record::new(&self.config, model, usage);
+        if let Err(e) = usage::append(&self.config.usage_path(), &record) {
+            self.warnings.push(format!("Failed to record usage: {:#}", e));
+        }
+    }
+
+    /// 开启了 `embed_history` 时计算这一轮对话的向量；失败时提示一次，本次不再重试
+    async fn embed_turn(&mut self, question: &str, answer: &str, model: String) {
+        if !self.config.embed_history || self.embed_failed {
+            return;
+        }
+        if let Err(e) = self.append_turn_vector(question, answer, model).await {
+            self.embed_failed = true;
+            self.warnings.push(format!(
+                "Failed to embed this turn for `history similar` (set embed_history = false to turn this off): {:#}",
+                e
+            ));
+        }
+    }
+
+    /// 计算一轮对话的向量并追加到历史向量文件
+    async fn append_turn_vector(&mut self, question: &str, answer: &str, model: String) -> Result<()> {
+        let embedding_model = self.config.embedding_model().to_string();
+        let text: String = index::history::text(question, answer).chars().take(MAX_EMBED_CHARS).collect();
+        let vector = self
+            .embed(&[text], &embedding_model)
+            .await?
+            .pop()
+            .context("The provider returned no embedding")?;
+        let entry = history::Entry {
+            time: Local::now(),
+            question: question.to_string(),
+            answer: answer.to_string(),
+            model: Some(model),
+            embedding_model,
+            vector,
+        };
+        history::append(&self.config.history_vectors_path(), &entry)
+    }
+
+    /// 在所有保存过向量的历史对话中找出和 `query` 意思最接近的 `k` 轮
+    pub async fn similar(&mut self, query: &str, k: usize) -> Result<Vec<history::Match>> {
+        let embedding_model = self.config.embedding_model().to_string();
+        let entries = history::load(&self.config.history_vectors_path(), &embedding_model)?;
+        if entries.is_empty() {
+            return Ok(Vec::new());
+        }
+        let vector = self
+            .embed(&[query.to_string()], &embedding_model)
+            .await?
+            .pop()
+            .context("The provider returned no embedding")?;
+        Ok(history::search(&entries, &vector, k))
+    }
+
+    /// 供 `--recall` 使用：和 [`Core::similar`] 相同，但跳过已经作为历史记录发送的对话
+    pub async fn recall(&mut self, query: &str, k: usize) -> Result<Vec<history::Match>> {
+        let mut matches = self.similar(query, k + self.config.get_history().len()).await?;
+        let history = self.config.get_history();
+        matches.retain(|item| {
+            !history
+                .iter()
+                .any(|turn| turn.question == item.question && turn.answer == item.answer)
+        });
+        matches.truncate(k);
+        Ok(matches)
+    }
+
+    /// 系统提示词 + 历史记录 + 本次问题
+    ///
+    /// 当前模型设置了 `context_window` 时，从最早的一轮开始丢弃历史记录，给回答留出 `max_tokens`
+    /// （未设置时为窗口的四分之一）的空间。
+    fn build_messages(&self, message: &str) -> Vec<Message> {
+        // 要求 JSON 回答时在系统提示后附上格式说明
+        let system_prompt = [
+            self.config.system_prompt.clone(),
+            self.structured.as_ref().map(StructuredOutput::instruction),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("\n\n");
+        let system = (!system_prompt.is_empty()).then(|| Message::new("system", system_prompt));
+        let question = Message::new("user", message);
+
+        let mut budget = match self.config.model_config().and_then(|model| model.context_window) {
+            Some(window) => {
+                let reserved = self.config.effective_max_tokens().unwrap_or(window / 4);
+                Some((window.saturating_sub(reserved) as usize)
+                    .saturating_sub(system.iter().chain([&question]).map(estimate_tokens).sum()))
+            }
+            None => None,
+        };
+
+        // 从最新的一轮往前取，直到超出预算
+        let mut history = Vec::new();
+        for item in self.config.get_history().iter().rev() {
+            // 之前的图片不再发送，只留下路径作为提示
+            let mut question = item.question.clone();
+            for path in &item.images {
+                question.push_str(&format!("\n[image: {}]", path.display()));
+            }
+            let pair = [
+                Message::new("user", question),
+                Message::new("assistant", item.answer.as_str()),
+            ];
+            if let Some(budget) = budget.as_mut() {
+                let cost: usize = pair.iter().map(estimate_tokens).sum();
+                if cost > *budget {
+                    break;
                 }
+                *budget -= cost;
             }
+            history.push(pair);
         }
-        println!();
 
-        // 保存对话历史
-        self.config.add_history(message.to_string(), full_response.clone());
-        
-        Ok(full_response)
+        system
+            .into_iter()
+            .chain(history.into_iter().rev().flatten())
+            .chain([question])
+            .collect()
+    }
+
+    /// 用 `transcription_model` 把音频文件转写为文字
+    pub async fn transcribe(&mut self, path: &Path) -> Result<String> {
+        let audio = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "audio".to_string());
+        let model = self.config.transcription_model().to_string();
+        self.bridge()?.transcribe(&model, &file_name, audio).await
+    }
+
+    /// 用 `speech_model` 合成语音，`voice` 为空时使用配置的音色
+    pub async fn speech(&mut self, text: &str, voice: Option<&str>, format: &str) -> Result<Vec<u8>> {
+        let request = SpeechRequest {
+            model: self.config.speech_model().to_string(),
+            input: text.to_string(),
+            voice: voice.map_or_else(|| self.config.voice(), str::to_string),
+            response_format: format.to_string(),
+        };
+        self.bridge()?.speech(&request).await
+    }
+
+    /// 生成图片并保存到 `dir`，返回保存的文件；这次生成和文件位置会记入历史
+    pub async fn generate_images(&mut self, request: &ImageRequest, dir: &Path) -> Result<Vec<PathBuf>> {
+        self.check_budget()?;
+        let images = self.bridge()?.generate_images(request).await?;
+        if images.is_empty() {
+            bail!("The provider returned no images");
+        }
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let stamp = Local::now().format("%Y%m%d-%H%M%S");
+        let mut paths = Vec::new();
+        let mut n = 0;
+        for bytes in &images {
+            let extension = image::guess_format(bytes)
+                .ok()
+                .and_then(|format| format.extensions_str().first().copied())
+                .unwrap_or("png");
+            // 同一秒内多次生成时不覆盖已有的文件
+            let path = loop {
+                n += 1;
+                let path = dir.join(format!("image-{}-{}.{}", stamp, n, extension));
+                if !path.exists() {
+                    break path;
+                }
+            };
+            fs::write(&path, bytes).with_context(|| format!("Failed to write {}", path.display()))?;
+            paths.push(path.canonicalize().unwrap_or(path));
+        }
+
+        let answer = paths
+            .iter()
+            .map(|path| format!("[image: {}]", path.display()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.save_turn(&request.prompt, &answer, Vec::new(), request.model.clone(), None)?;
+        Ok(paths)
+    }
+
+    /// 用 `model` 计算一组文本的向量，输入较多时分批请求；和对话一样检查预算并记录用量
+    pub async fn embed(&mut self, inputs: &[String], model: &str) -> Result<Vec<Vec<f32>>> {
+        self.check_budget()?;
+        let mut vectors = Vec::with_capacity(inputs.len());
+        for batch in inputs.chunks(EMBED_BATCH) {
+            let (batch, usage) = self.bridge()?.embed(model, batch).await?;
+            vectors.extend(batch);
+            if let Some(usage) = usage {
+                self.record_usage(model, &usage);
+            }
+        }
+        Ok(vectors)
+    }
+
+    /// 把 `dir` 下的文本文件加入索引 `name`，只处理新增和修改过的文件，返回处理的文件数和片段数
+    pub async fn index_add(&mut self, name: &str, dir: &Path) -> Result<(usize, usize)> {
+        let index_dir = self.config.index_dir();
+        let model = self.config.embedding_model().to_string();
+        let mut index = Index::load(&index_dir, name)?.unwrap_or_else(|| Index::new(&model));
+        if index.model != model {
+            bail!(
+                "Index `{}` was built with {}, but embedding_model is {} (remove the index to rebuild it)",
+                name,
+                index.model,
+                model
+            );
+        }
+
+        let dir = dir.canonicalize().with_context(|| format!("Failed to open {}", dir.display()))?;
+        let files = index::collect_files(&dir)?;
+        let stale = index.stale_files(&dir, files);
+        let pieces: Vec<(&PathBuf, usize, usize, String)> = stale
+            .iter()
+            .flat_map(|file| {
+                index::split(&file.content)
+                    .into_iter()
+                    .map(move |(start, end, text)| (&file.path, start, end, text))
+            })
+            .collect();
+        let texts: Vec<String> = pieces.iter().map(|(.., text)| text.clone()).collect();
+        let vectors = self.embed(&texts, &model).await?;
+
+        for ((path, start_line, end_line, text), vector) in pieces.into_iter().zip(vectors) {
+            index.chunks.push(Chunk {
+                path: path.clone(),
+                start_line,
+                end_line,
+                text,
+                vector,
+            });
+        }
+        for file in &stale {
+            index.files.insert(file.path.clone(), file.modified);
+        }
+        index.save(&index_dir, name)?;
+        Ok((stale.len(), texts.len()))
+    }
+
+    /// 从索引 `name` 中找出和 `query` 最相关的 `k` 个片段，索引开启了重排序时先多取一些候选再重排
+    pub async fn retrieve(&mut self, name: &str, query: &str, k: usize) -> Result<Vec<Hit>> {
+        let index = Index::load(&self.config.index_dir(), name)?
+            .with_context(|| format!("No index named `{}` (create it with `chat index add <dir> --name {}`)", name, name))?;
+        let vector = self
+            .embed(&[query.to_string()], &index.model)
+            .await?
+            .pop()
+            .context("The provider returned no embedding")?;
+
+        let Some(rerank_model) = self.config.rerank_model(name).map(str::to_string) else {
+            return Ok(index.search(&vector, k));
+        };
+        let candidates = self.config.indexes[name].candidates.unwrap_or(k * 4).max(k);
+        let hits = index.search(&vector, candidates);
+        if hits.is_empty() {
+            return Ok(hits);
+        }
+        let documents: Vec<String> = hits.iter().map(|hit| hit.text.clone()).collect();
+        let results = self.bridge()?.rerank(&rerank_model, query, &documents, k).await?;
+        Ok(results
+            .into_iter()
+            .map(|result| Hit {
+                rerank_score: Some(result.relevance_score),
+                ..hits[result.index].clone()
+            })
+            .collect())
+    }
+
+    /// 服务商提供的模型列表，优先使用未过期的缓存
+    pub async fn models(&mut self, refresh: bool) -> Result<Vec<ModelInfo>> {
+        let api_base = self.config.effective_api_base().to_string();
+        if !refresh {
+            if let Some(list) = models::cached(&api_base, models::CACHE_TTL) {
+                return Ok(list);
+            }
+        }
+        let list = self.bridge()?.list_models().await?;
+        if let Err(e) = models::store(&api_base, &list) {
+            self.warnings.push(format!("Failed to cache model list: {:#}", e));
+        }
+        Ok(list)
     }
 
     pub fn clear_history(&mut self) -> Result<()> {
@@ -100,16 +754,128 @@ impl Core {
     pub fn update_config(&mut self, new_config: Config) -> Result<()> {
         new_config.save()?;
         self.config = new_config;
-        self.bridge = Bridge::new(
-            self.config.api_base.clone(),
-            self.config.token.clone(),
-            self.config.model.clone(),
-        );
+        self.bridge = None;
         Ok(())
     }
 }
 
-// TODO: 实现核心 API 功能
+/// 把流式返回的工具调用片段按 `index` 拼接起来
+fn assemble_tool_call(calls: &mut Vec<ToolCall>, delta: ToolCallDelta) {
+    while calls.len() <= delta.index {
+        calls.push(ToolCall {
+            id: String::new(),
+            kind: "function".to_string(),
+            function: FunctionCall {
+                name: String::new(),
+                arguments: String::new(),
+            },
+        });
+    }
+    let call = &mut calls[delta.index];
+    if let Some(id) = delta.id {
+        call.id = id;
+    }
+    if let Some(name) = delta.name {
+        call.function.name = name;
+    }
+    if let Some(arguments) = delta.arguments {
+        call.function.arguments.push_str(&arguments);
+    }
+}
+
+fn truncate(mut output: String) -> String {
+    if output.chars().count() > MAX_TOOL_OUTPUT {
+        output = output.chars().take(MAX_TOOL_OUTPUT).collect();
+        output.push_str("\n[output truncated]");
+    }
+    output
+}
+
+/// 粗略估计一条消息的 token 数：ASCII 字符约 4 个一个 token，其他字符（如汉字）各算一个
+fn estimate_tokens(message: &Message) -> usize {
+    let (ascii, other) = message.content.chars().fold((0usize, 0usize), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    // 每条消息还有角色等少量开销
+    ascii.div_ceil(4) + other + 4
+}
+
+/// 按名字使用的 prompt 模板
+///
+/// 目前的来源是 MCP 服务器，名字为 `服务器:prompt`。
+#[derive(Default)]
 pub struct PromptManager {
-    // TODO: 实现 prompt 管理
-} 
\ No newline at end of file
+    entries: BTreeMap<String, PromptEntry>,
+}
+
+pub struct PromptEntry {
+    pub name: String,
+    pub description: Option<String>,
+    pub arguments: Vec<PromptArgument>,
+    source: PromptSource,
+}
+
+enum PromptSource {
+    Mcp { client: Arc<McpClient>, name: String },
+}
+
+impl PromptManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 加入一个 MCP 服务器提供的全部 prompt
+    pub fn add_mcp(&mut self, client: &Arc<McpClient>) -> Result<()> {
+        for prompt in client.list_prompts()? {
+            let name = format!("{}:{}", client.name(), prompt.name);
+            self.entries.insert(
+                name.clone(),
+                PromptEntry {
+                    name,
+                    description: prompt.description,
+                    arguments: prompt.arguments,
+                    source: PromptSource::Mcp {
+                        client: client.clone(),
+                        name: prompt.name,
+                    },
+                },
+            );
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = &PromptEntry> {
+        self.entries.values()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PromptEntry> {
+        self.entries.get(name)
+    }
+
+    /// 展开 prompt 得到消息；未知的名字和缺少必填参数都会报错
+    pub fn render(&self, name: &str, arguments: &BTreeMap<String, String>) -> Result<Vec<Message>> {
+        let Some(entry) = self.get(name) else {
+            let known: Vec<&str> = self.entries.keys().map(String::as_str).collect();
+            if known.is_empty() {
+                bail!("Unknown prompt `{}`: no MCP server provides prompts", name);
+            }
+            bail!("Unknown prompt `{}` (available: {})", name, known.join(", "));
+        };
+        let missing: Vec<&str> = entry
+            .arguments
+            .iter()
+            .filter(|argument| argument.required && !arguments.contains_key(&argument.name))
+            .map(|argument| argument.name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            bail!("Prompt `{}` needs argument(s): {}", name, missing.join(", "));
+        }
+        match &entry.source {
+            PromptSource::Mcp { client, name } => client.get_prompt(name, arguments),
+        }
+    }
+}
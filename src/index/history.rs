@@ -0,0 +1,98 @@
+use super::cosine;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// 历史记录中一轮对话的向量，单独追加保存，不受 `max_history` 限制
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Local>,
+    pub question: String,
+    pub answer: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// 生成向量的模型，换了模型后旧的向量不再参与搜索
+    pub embedding_model: String,
+    pub vector: Vec<f32>,
+}
+
+/// 搜索结果
+#[derive(Debug, Clone, Serialize)]
+pub struct Match {
+    pub time: DateTime<Local>,
+    pub question: String,
+    pub answer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub score: f32,
+}
+
+/// 用来生成向量的文本
+pub fn text(question: &str, answer: &str) -> String {
+    format!("Q: {}\nA: {}", question, answer)
+}
+
+/// 追加一条记录，和用量记录一样一次写入一行
+pub fn append(path: &Path, entry: &Entry) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create data directory")?;
+    }
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// 读取 `embedding_model` 生成的全部记录，无法解析的行会被跳过
+pub fn load(path: &Path, embedding_model: &str) -> Result<Vec<Entry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+        .filter(|entry| entry.embedding_model == embedding_model)
+        .collect())
+}
+
+/// 和 `query` 最相似的 `k` 轮对话
+pub fn search(entries: &[Entry], query: &[f32], k: usize) -> Vec<Match> {
+    let mut matches: Vec<Match> = entries
+        .iter()
+        .map(|entry| Match {
+            time: entry.time,
+            question: entry.question.clone(),
+            answer: entry.answer.clone(),
+            model: entry.model.clone(),
+            score: cosine(query, &entry.vector),
+        })
+        .collect();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(k);
+    matches
+}
+
+/// 把找到的对话整理成放在问题前面的参考资料
+pub fn context(matches: &[Match]) -> String {
+    let mut context = String::from(
+        "Earlier conversations that may be relevant to the question below. Use them only if they help.\n",
+    );
+    for item in matches {
+        context.push_str(&format!(
+            "\n[{}]\nQ: {}\nA: {}\n",
+            item.time.format("%Y-%m-%d"),
+            item.question,
+            item.answer.trim_end()
+        ));
+    }
+    context
+}
@@ -0,0 +1,312 @@
+use crate::config::store;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use walkdir::WalkDir;
+
+pub mod history;
+
+/// 每个片段的最大长度（字符），按行切分，只有超长的行才会被切断
+pub const CHUNK_CHARS: usize = 1500;
+
+/// 跳过的大文件
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// 一个本地文档索引：文本片段和它们的向量，保存为一个 JSON 文件
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Index {
+    /// 生成向量的模型，查询时必须用同一个模型
+    pub model: String,
+    /// 已索引文件的修改时间（Unix 秒），再次添加时跳过没有变化的文件
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, u64>,
+    #[serde(default)]
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    pub path: PathBuf,
+    /// 起止行号（从 1 开始，包含两端）
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+/// 检索结果
+#[derive(Debug, Clone, Serialize)]
+pub struct Hit {
+    pub path: PathBuf,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    /// 和问题向量的余弦相似度
+    pub score: f32,
+    /// 经过重排序时的相关性分数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerank_score: Option<f32>,
+}
+
+/// 一个待索引的文件
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub modified: u64,
+    pub content: String,
+}
+
+impl Index {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            files: BTreeMap::new(),
+            chunks: Vec::new(),
+        }
+    }
+
+    /// 索引文件路径；名字不能含路径分隔符，也不能以 `.` 开头，不会指向 `dir` 之外
+    pub fn path(dir: &Path, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            bail!("Invalid index name `{}`: use a plain name without path separators", name);
+        }
+        Ok(dir.join(format!("{}.json", name)))
+    }
+
+    /// 读取索引，不存在时返回 `None`
+    pub fn load(dir: &Path, name: &str) -> Result<Option<Self>> {
+        let path = Self::path(dir, name)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let index = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse index {}", path.display()))?;
+        Ok(Some(index))
+    }
+
+    pub fn save(&self, dir: &Path, name: &str) -> Result<()> {
+        let path = Self::path(dir, name)?;
+        let content = serde_json::to_string(self)?;
+        store::with_lock(&path, || store::write_atomic(&path, &content))
+    }
+
+    /// 删除索引，不存在时报错
+    pub fn remove(dir: &Path, name: &str) -> Result<()> {
+        let path = Self::path(dir, name)?;
+        if !path.exists() {
+            bail!("No index named `{}`", name);
+        }
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        for suffix in ["bak", "lock"] {
+            let _ = fs::remove_file(path.with_extension(format!("json.{}", suffix)));
+        }
+        Ok(())
+    }
+
+    /// 已保存的索引名
+    pub fn list(dir: &Path) -> Result<Vec<String>> {
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names: Vec<String> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// 去掉 `dir` 下已删除或已修改的文件，返回需要（重新）索引的文件
+    pub fn stale_files(&mut self, dir: &Path, files: Vec<SourceFile>) -> Vec<SourceFile> {
+        let present: Vec<&PathBuf> = files.iter().map(|file| &file.path).collect();
+        let removed: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|path| path.starts_with(dir) && !present.contains(path))
+            .cloned()
+            .collect();
+        for path in &removed {
+            self.remove_file(path);
+        }
+
+        let changed: Vec<SourceFile> = files
+            .into_iter()
+            .filter(|file| self.files.get(&file.path) != Some(&file.modified))
+            .collect();
+        for file in &changed {
+            self.remove_file(&file.path);
+        }
+        changed
+    }
+
+    fn remove_file(&mut self, path: &Path) {
+        self.files.remove(path);
+        self.chunks.retain(|chunk| chunk.path != path);
+    }
+
+    /// 余弦相似度最高的 `k` 个片段
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<Hit> {
+        let mut hits: Vec<Hit> = self
+            .chunks
+            .iter()
+            .map(|chunk| Hit {
+                path: chunk.path.clone(),
+                start_line: chunk.start_line,
+                end_line: chunk.end_line,
+                text: chunk.text.clone(),
+                score: cosine(query, &chunk.vector),
+                rerank_score: None,
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(k);
+        hits
+    }
+}
+
+/// 找出 `dir` 下的文本文件，跳过隐藏文件、`target`、`node_modules` 以及二进制和过大的文件
+pub fn collect_files(dir: &Path) -> Result<Vec<SourceFile>> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("Failed to open {}", dir.display()))?;
+    let walker = WalkDir::new(&dir).into_iter().filter_entry(|entry| {
+        let name = entry.file_name().to_string_lossy();
+        entry.depth() == 0 || !(name.starts_with('.') || name == "target" || name == "node_modules")
+    });
+    let mut files = Vec::new();
+    for entry in walker.filter_map(|entry| entry.ok()) {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() || metadata.len() > MAX_FILE_SIZE {
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        if content.trim().is_empty() {
+            continue;
+        }
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_secs());
+        files.push(SourceFile {
+            path: entry.into_path(),
+            modified,
+            content,
+        });
+    }
+    if files.is_empty() {
+        bail!("No text files found in {}", dir.display());
+    }
+    Ok(files)
+}
+
+/// 按行把文件切成不超过 [`CHUNK_CHARS`] 个字符的片段，返回（起始行，结束行，文本）
+pub fn split(content: &str) -> Vec<(usize, usize, String)> {
+    let mut chunks = Vec::new();
+    let mut text = String::new();
+    let mut length = 0;
+    let (mut start, mut end) = (1, 0);
+    for (i, line) in content.lines().enumerate() {
+        // 超过上限的一行（例如压缩过的代码）按字符硬切，否则这个片段会让 embedding 请求失败
+        let chars: Vec<char> = line.chars().collect();
+        let pieces: Vec<&[char]> = if chars.is_empty() {
+            vec![&[]]
+        } else {
+            chars.chunks(CHUNK_CHARS - 1).collect()
+        };
+        for piece in pieces {
+            if !text.is_empty() && length + piece.len() + 1 > CHUNK_CHARS {
+                chunks.push((start, end, std::mem::take(&mut text)));
+                length = 0;
+            }
+            if text.is_empty() {
+                start = i + 1;
+            }
+            text.extend(piece);
+            text.push('\n');
+            length += piece.len() + 1;
+            end = i + 1;
+        }
+    }
+    if !text.trim().is_empty() {
+        chunks.push((start, end, text));
+    }
+    chunks
+}
+
+/// 把检索到的片段整理成放在问题前面的参考资料，要求模型用编号引用
+pub fn context(hits: &[Hit]) -> String {
+    let mut context = String::from(
+        "Answer the question using the numbered excerpts below when they are relevant, and cite them like [1]. \
+         Say so if they don't contain the answer.\n",
+    );
+    for (i, hit) in hits.iter().enumerate() {
+        context.push_str(&format!(
+            "\n[{}] {}:{}-{}\n```\n{}```\n",
+            i + 1,
+            hit.path.display(),
+            hit.start_line,
+            hit.end_line,
+            hit.text
+        ));
+    }
+    context
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_names_stay_in_the_index_directory() {
+        let dir = Path::new("/data/indexes");
+        assert_eq!(Index::path(dir, "docs").unwrap(), dir.join("docs.json"));
+        for name in ["", "..", "../x", "../../x", "a/b", "a\\b", ".hidden"] {
+            assert!(Index::path(dir, name).is_err(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn split_keeps_lines_together() {
+        let line = "x".repeat(CHUNK_CHARS / 4);
+        let content = [line.as_str(); 5].join("\n");
+        let chunks = split(&content);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!((chunks[0].0, chunks[0].1), (1, 3));
+        assert_eq!((chunks[1].0, chunks[1].1), (4, 5));
+        assert_eq!(chunks[1].2.lines().count(), 2);
+    }
+
+    #[test]
+    fn split_hard_splits_long_lines() {
+        let content = format!("first\n{}\nlast\n", "é".repeat(CHUNK_CHARS * 3));
+        let chunks = split(&content);
+        assert!(chunks.iter().all(|(_, _, text)| text.chars().count() <= CHUNK_CHARS));
+        assert_eq!((chunks[0].0, chunks[0].1), (1, 1));
+        assert!(chunks[1..chunks.len() - 1].iter().all(|(start, end, _)| (*start, *end) == (2, 2)));
+        assert_eq!(chunks.last().unwrap().1, 3);
+        let joined: String = chunks.iter().map(|(_, _, text)| text.as_str()).collect();
+        assert_eq!(joined.chars().filter(|c| *c == 'é').count(), CHUNK_CHARS * 3);
+    }
+}
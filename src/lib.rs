@@ -2,7 +2,17 @@ pub mod cli;
 pub mod core;
 pub mod bridge;
 pub mod config;
+pub mod code;
+pub mod render;
+pub mod server;
+pub mod models;
+pub mod usage;
+pub mod tools;
+pub mod mcp;
+pub mod structured;
+pub mod vision;
+pub mod index;
 
-pub async fn run_cli() -> Result<(), Box<dyn std::error::Error>> {
+pub async fn run_cli() -> anyhow::Result<()> {
     cli::run().await
 } 
\ No newline at end of file
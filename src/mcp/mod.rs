@@ -0,0 +1,483 @@
+use crate::bridge::Message;
+use crate::config::McpServerConfig;
+use crate::tools::{Tool, ToolRegistry};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// 一个通过 stdio 连接的 MCP 服务器
+///
+/// 消息是逐行的 JSON-RPC；调用是同步的，和其他工具一样在执行期间阻塞。
+pub struct McpClient {
+    name: String,
+    timeout: Duration,
+    io: Mutex<Io>,
+    capabilities: Value,
+}
+
+struct Io {
+    child: Child,
+    stdin: ChildStdin,
+    /// 读线程逐行转发服务器的输出，读到末尾时关闭
+    lines: Receiver<String>,
+    next_id: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct McpTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename = "inputSchema", default)]
+    pub input_schema: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct McpResource {
+    pub uri: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename = "mimeType", default)]
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct McpPrompt {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub arguments: Vec<PromptArgument>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptArgument {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+impl McpClient {
+    /// 启动服务器进程并完成初始化握手
+    pub fn connect(name: &str, config: &McpServerConfig) -> Result<Self> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to start MCP server `{}` ({})", name, config.command))?;
+        let stdin = child.stdin.take().context("Failed to open stdin of MCP server")?;
+        let stdout = child.stdout.take().context("Failed to open stdout of MCP server")?;
+
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            name: name.to_string(),
+            timeout: Duration::from_secs(config.timeout),
+            io: Mutex::new(Io {
+                child,
+                stdin,
+                lines,
+                next_id: 1,
+            }),
+            capabilities: Value::Null,
+        };
+        let result = client.request(
+            "initialize",
+            json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {},
+                "clientInfo": { "name": "chat", "version": env!("CARGO_PKG_VERSION") },
+            }),
+        )?;
+        client.capabilities = result["capabilities"].clone();
+        client.notify("notifications/initialized", json!({}))?;
+        Ok(client)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn supports(&self, capability: &str) -> bool {
+        self.capabilities.get(capability).is_some()
+    }
+
+    pub fn list_tools(&self) -> Result<Vec<McpTool>> {
+        if !self.supports("tools") {
+            return Ok(Vec::new());
+        }
+        self.list("tools/list", "tools")
+    }
+
+    pub fn list_resources(&self) -> Result<Vec<McpResource>> {
+        if !self.supports("resources") {
+            return Ok(Vec::new());
+        }
+        self.list("resources/list", "resources")
+    }
+
+    pub fn list_prompts(&self) -> Result<Vec<McpPrompt>> {
+        if !self.supports("prompts") {
+            return Ok(Vec::new());
+        }
+        self.list("prompts/list", "prompts")
+    }
+
+    /// 调用工具，把返回的内容整理成文本
+    pub fn call_tool(&self, name: &str, arguments: &Value) -> Result<String> {
+        let result = self.request("tools/call", json!({ "name": name, "arguments": arguments }))?;
+        let text = content_text(&result["content"]);
+        if result["isError"].as_bool().unwrap_or(false) {
+            bail!("{}", text);
+        }
+        Ok(text)
+    }
+
+    pub fn read_resource(&self, uri: &str) -> Result<String> {
+        let result = self.request("resources/read", json!({ "uri": uri }))?;
+        let contents = result["contents"].as_array().cloned().unwrap_or_default();
+        Ok(contents
+            .iter()
+            .map(|content| match content["text"].as_str() {
+                Some(text) => text.to_string(),
+                None => format!("[binary resource {}]", content["uri"].as_str().unwrap_or(uri)),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// 展开一个 prompt，得到其中的消息
+    pub fn get_prompt(&self, name: &str, arguments: &BTreeMap<String, String>) -> Result<Vec<Message>> {
+        let result = self.request("prompts/get", json!({ "name": name, "arguments": arguments }))?;
+        let messages = result["messages"].as_array().cloned().unwrap_or_default();
+        Ok(messages
+            .iter()
+            .map(|message| {
+                let role = message["role"].as_str().unwrap_or("user");
+                Message::new(role, content_text(&Value::Array(vec![message["content"].clone()])))
+            })
+            .collect())
+    }
+
+    /// 读取分页的列表
+    fn list<T: for<'de> Deserialize<'de>>(&self, method: &str, field: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let mut result = self.request(method, params)?;
+            let page: Vec<T> = serde_json::from_value(result[field].take())
+                .with_context(|| format!("Invalid {} response from MCP server `{}`", method, self.name))?;
+            items.extend(page);
+            match result["nextCursor"].as_str() {
+                Some(next) => cursor = Some(next.to_string()),
+                None => return Ok(items),
+            }
+        }
+    }
+
+    fn notify(&self, method: &str, params: Value) -> Result<()> {
+        let mut io = self.io.lock().map_err(|_| anyhow!("MCP connection is poisoned"))?;
+        io.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let mut io = self.io.lock().map_err(|_| anyhow!("MCP connection is poisoned"))?;
+        let id = io.next_id;
+        io.next_id += 1;
+        io.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+
+        loop {
+            let line = match io.lines.recv_timeout(self.timeout) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    bail!("MCP server `{}` did not answer {} within {:?}", self.name, method, self.timeout)
+                }
+                Err(RecvTimeoutError::Disconnected) => bail!("MCP server `{}` exited", self.name),
+            };
+            // 服务器也可能在 stdout 上输出非 JSON 的日志，忽略即可
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if message.get("method").is_some() {
+                // 服务器发来的请求：只回应 ping，其余一律不支持；通知直接忽略
+                if let Some(request_id) = message.get("id") {
+                    let reply = if message["method"] == "ping" {
+                        json!({ "jsonrpc": "2.0", "id": request_id, "result": {} })
+                    } else {
+                        json!({ "jsonrpc": "2.0", "id": request_id, "error": { "code": -32601, "message": "Method not found" } })
+                    };
+                    io.send(&reply)?;
+                }
+                continue;
+            }
+            if message["id"].as_u64() != Some(id) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                bail!(
+                    "MCP server `{}` failed {}: {}",
+                    self.name,
+                    method,
+                    error["message"].as_str().unwrap_or("unknown error")
+                );
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+}
+
+impl Io {
+    fn send(&mut self, message: &Value) -> Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.stdin
+            .write_all(line.as_bytes())
+            .and_then(|_| self.stdin.flush())
+            .context("Failed to write to MCP server")
+    }
+}
+
+impl Drop for McpClient {
+    fn drop(&mut self) {
+        if let Ok(io) = self.io.get_mut() {
+            let _ = io.child.kill();
+            let _ = io.child.wait();
+        }
+    }
+}
+
+/// 把 MCP 的内容块列表转成文本，非文本内容只留一个占位说明
+fn content_text(content: &Value) -> String {
+    content
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|item| match item["type"].as_str() {
+            Some("text") => item["text"].as_str().unwrap_or_default().to_string(),
+            Some("resource") => match item["resource"]["text"].as_str() {
+                Some(text) => text.to_string(),
+                None => format!("[resource {}]", item["resource"]["uri"].as_str().unwrap_or_default()),
+            },
+            Some("resource_link") => format!("[resource {}]", item["uri"].as_str().unwrap_or_default()),
+            Some(kind) => format!("[{} content]", kind),
+            None => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 模型看到的工具名：`服务器__工具`，只保留函数名允许的字符
+pub fn tool_name(server: &str, tool: &str) -> String {
+    format!("{}__{}", server, tool)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .take(64)
+        .collect()
+}
+
+/// 把服务器的工具和资源注册为可供模型调用的工具
+pub fn register(registry: &mut ToolRegistry, client: &Arc<McpClient>, confirm: bool) -> Result<()> {
+    for tool in client.list_tools()? {
+        registry.register(RemoteTool {
+            name: tool_name(client.name(), &tool.name),
+            description: tool.description.clone().unwrap_or_default(),
+            client: client.clone(),
+            tool,
+            confirm,
+        });
+    }
+    let resources = client.list_resources()?;
+    if !resources.is_empty() {
+        let mut description = format!("Read a resource of the `{}` MCP server by URI. Available resources:", client.name());
+        for resource in &resources {
+            description.push_str(&format!("\n- {} ({})", resource.uri, resource.name));
+            if let Some(text) = &resource.description {
+                description.push_str(&format!(": {}", text));
+            }
+        }
+        registry.register(ReadResource {
+            name: tool_name(client.name(), "read_resource"),
+            description,
+            client: client.clone(),
+        });
+    }
+    Ok(())
+}
+
+struct RemoteTool {
+    name: String,
+    description: String,
+    client: Arc<McpClient>,
+    tool: McpTool,
+    confirm: bool,
+}
+
+impl Tool for RemoteTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        if self.tool.input_schema.is_object() {
+            self.tool.input_schema.clone()
+        } else {
+            json!({ "type": "object", "properties": {} })
+        }
+    }
+
+    fn needs_confirmation(&self) -> bool {
+        self.confirm
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String> {
+        self.client.call_tool(&self.tool.name, arguments)
+    }
+}
+
+struct ReadResource {
+    name: String,
+    description: String,
+    client: Arc<McpClient>,
+}
+
+impl Tool for ReadResource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "uri": { "type": "string" },
+            },
+            "required": ["uri"],
+        })
+    }
+
+    fn needs_confirmation(&self) -> bool {
+        false
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String> {
+        let uri = arguments["uri"].as_str().context("Missing string argument `uri`")?;
+        self.client.read_resource(uri)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::core::Core;
+    use std::path::Path;
+
+    /// 最小的 stdio MCP 服务器：一个 `add` 工具和一个 `review` prompt，启动时先输出一行非 JSON 日志
+    const STAND_IN: &str = r#"
+[ -n "$STARTED" ] && touch "$STARTED"
+echo "stand-in starting"
+while IFS= read -r line; do
+  id=$(printf '%s\n' "$line" | sed -n 's/.*"id":\([0-9][0-9]*\).*/\1/p')
+  [ -n "$id" ] || continue
+  case "$line" in
+    *'"method":"initialize"'*) result='{"protocolVersion":"2025-06-18","capabilities":{"tools":{},"prompts":{}},"serverInfo":{"name":"stand-in","version":"1"}}' ;;
+    *'"method":"tools/list"'*) result='{"tools":[{"name":"add","description":"Add two numbers","inputSchema":{"type":"object"}}]}' ;;
+    *'"method":"tools/call"'*'"name":"add"'*) result='{"content":[{"type":"text","text":"3"}]}' ;;
+    *'"method":"tools/call"'*) result='{"content":[{"type":"text","text":"boom"}],"isError":true}' ;;
+    *'"method":"prompts/list"'*) result='{"prompts":[{"name":"review","arguments":[{"name":"lang","required":true}]}]}' ;;
+    *'"method":"prompts/get"'*) result='{"messages":[{"role":"user","content":{"type":"text","text":"Review this code."}}]}' ;;
+    *) printf '{"jsonrpc":"2.0","id":%s,"error":{"code":-32601,"message":"Method not found"}}\n' "$id"; continue ;;
+  esac
+  printf '{"jsonrpc":"2.0","id":%s,"result":%s}\n' "$id" "$result"
+done
+"#;
+
+    fn stand_in(started: Option<&Path>) -> McpServerConfig {
+        McpServerConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), STAND_IN.to_string()],
+            env: started
+                .map(|path| ("STARTED".to_string(), path.display().to_string()))
+                .into_iter()
+                .collect(),
+            confirm: false,
+            timeout: 10,
+        }
+    }
+
+    #[test]
+    fn talks_to_a_stdio_server() {
+        let client = Arc::new(McpClient::connect("local", &stand_in(None)).unwrap());
+        let tools = client.list_tools().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "add");
+        assert!(client.list_resources().unwrap().is_empty());
+        assert_eq!(client.call_tool("add", &json!({ "a": 1, "b": 2 })).unwrap(), "3");
+        assert_eq!(client.call_tool("missing", &json!({})).unwrap_err().to_string(), "boom");
+
+        let prompts = client.list_prompts().unwrap();
+        assert_eq!(prompts[0].name, "review");
+        assert!(prompts[0].arguments[0].required);
+        let arguments = BTreeMap::from([("lang".to_string(), "rust".to_string())]);
+        let messages = client.get_prompt("review", &arguments).unwrap();
+        assert_eq!(messages[0].content, "Review this code.");
+
+        let mut registry = ToolRegistry::new();
+        register(&mut registry, &client, false).unwrap();
+        let names: Vec<String> = registry.definitions().into_iter().map(|tool| tool.function.name).collect();
+        assert_eq!(names, ["local__add"]);
+    }
+
+    #[test]
+    fn servers_start_only_when_connected() {
+        let started = std::env::temp_dir().join(format!("chat-mcp-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&started);
+        let mut config = Config::default();
+        config.mcp_servers.insert("local".to_string(), stand_in(Some(&started)));
+
+        let mut core = Core::with_config(config);
+        assert!(core.tools_mut().definitions().is_empty());
+        assert!(!started.exists());
+
+        assert_eq!(core.connect_mcp().len(), 1);
+        assert!(started.exists());
+        assert!(core.tools_mut().get("local__add").is_some());
+        assert!(core.prompts().get("local:review").is_some());
+        let _ = std::fs::remove_file(&started);
+    }
+}
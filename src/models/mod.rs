@@ -0,0 +1,161 @@
+use crate::bridge::ModelInfo;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 模型列表缓存的有效期
+pub const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// 按 `api_base` 分别缓存，切换服务商时不会拿到别家的模型
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Cache {
+    #[serde(default)]
+    providers: BTreeMap<String, CacheEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// 获取时间（Unix 秒）
+    fetched_at: u64,
+    models: Vec<ModelInfo>,
+}
+
+fn cache_path() -> Result<PathBuf> {
+    let cache_dir = dirs::cache_dir().context("Failed to get cache directory")?;
+    Ok(cache_dir.join("chat").join("models.json"))
+}
+
+fn read_cache(path: &Path) -> Cache {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// 读取缓存的模型列表，超过 `max_age` 的视为不存在
+pub fn cached(api_base: &str, max_age: Duration) -> Option<Vec<ModelInfo>> {
+    cached_in(&cache_path().ok()?, api_base, max_age)
+}
+
+fn cached_in(path: &Path, api_base: &str, max_age: Duration) -> Option<Vec<ModelInfo>> {
+    let entry = read_cache(path).providers.remove(api_base)?;
+    let age = Duration::from_secs(now().saturating_sub(entry.fetched_at));
+    (age <= max_age).then_some(entry.models)
+}
+
+/// 更新缓存；缓存丢了只会多请求一次，因此不需要加锁
+pub fn store(api_base: &str, models: &[ModelInfo]) -> Result<()> {
+    store_in(&cache_path()?, api_base, models, now())
+}
+
+fn store_in(path: &Path, api_base: &str, models: &[ModelInfo], fetched_at: u64) -> Result<()> {
+    let mut cache = read_cache(path);
+    cache.providers.insert(
+        api_base.to_string(),
+        CacheEntry {
+            fetched_at,
+            models: models.to_vec(),
+        },
+    );
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create cache directory")?;
+    }
+    fs::write(path, serde_json::to_string(&cache)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// 按子串过滤（不区分大小写）
+pub fn filter<'a>(models: &'a [ModelInfo], pattern: &str) -> Vec<&'a ModelInfo> {
+    let pattern = pattern.to_lowercase();
+    models
+        .iter()
+        .filter(|model| model.id.to_lowercase().contains(&pattern))
+        .collect()
+}
+
+/// 找出和 `name` 相近的模型 ID，用于拼错时提示
+///
+/// 同时和完整 ID 以及去掉 `组织/` 前缀的部分比较，`deepseek-v3` 也能匹配到 `deepseek-ai/DeepSeek-V3`。
+pub fn suggest<'a>(name: &str, models: &'a [ModelInfo]) -> Vec<&'a str> {
+    let name = name.to_lowercase();
+    let mut scored: Vec<(f64, &str)> = models
+        .iter()
+        .map(|model| {
+            let id = model.id.to_lowercase();
+            let short = id.rsplit('/').next().unwrap_or(&id);
+            let score = strsim::jaro_winkler(&name, &id).max(strsim::jaro_winkler(&name, short));
+            let score = if id.contains(&name) { score.max(0.9) } else { score };
+            (score, model.id.as_str())
+        })
+        .filter(|(score, _)| *score >= 0.8)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.into_iter().take(5).map(|(_, id)| id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn models(ids: &[&str]) -> Vec<ModelInfo> {
+        ids.iter()
+            .map(|id| ModelInfo {
+                id: id.to_string(),
+                owned_by: None,
+                created: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn suggests_close_model_ids() {
+        let list = models(&["deepseek-ai/DeepSeek-V3", "deepseek-ai/DeepSeek-R1", "Qwen/Qwen2.5-72B-Instruct", "gpt-4o", "gpt-4o-mini"]);
+        assert_eq!(suggest("deepseek-v3", &list)[0], "deepseek-ai/DeepSeek-V3");
+        assert_eq!(suggest("DeepSeek-V3", &list)[0], "deepseek-ai/DeepSeek-V3");
+        assert_eq!(suggest("gpt4o", &list)[..2], ["gpt-4o", "gpt-4o-mini"]);
+        // 子串匹配总会被提示
+        assert!(suggest("qwen2.5", &list).contains(&"Qwen/Qwen2.5-72B-Instruct"));
+        assert!(suggest("llama-3-70b", &list).is_empty());
+        assert!(suggest("gpt", &models(&["a", "b"])).is_empty());
+        assert!(suggest("gpt", &list).len() <= 5);
+    }
+
+    #[test]
+    fn filter_ignores_case() {
+        let list = models(&["deepseek-ai/DeepSeek-V3", "Qwen/QwQ-32B"]);
+        let ids: Vec<&str> = filter(&list, "DEEPSEEK").iter().map(|model| model.id.as_str()).collect();
+        assert_eq!(ids, ["deepseek-ai/DeepSeek-V3"]);
+    }
+
+    #[test]
+    fn cache_expires_and_is_kept_per_provider() {
+        let dir = std::env::temp_dir().join(format!("chat-models-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("models.json");
+        let (a, b) = ("https://a.example/v1", "https://b.example/v1");
+        assert!(cached_in(&path, a, Duration::MAX).is_none());
+
+        let day = CACHE_TTL.as_secs();
+        store_in(&path, a, &models(&["fresh"]), now() - day / 2).unwrap();
+        store_in(&path, b, &models(&["stale"]), now() - day - 60).unwrap();
+
+        assert_eq!(cached_in(&path, a, CACHE_TTL).unwrap()[0].id, "fresh");
+        assert!(cached_in(&path, b, CACHE_TTL).is_none());
+        // 补全时不在乎过期，`Duration::MAX` 总能读到
+        assert_eq!(cached_in(&path, b, Duration::MAX).unwrap()[0].id, "stale");
+        assert!(cached_in(&path, a, Duration::from_secs(60)).is_none());
+        assert!(cached_in(&path, "https://c.example/v1", Duration::MAX).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,623 @@
+use clap::ValueEnum;
+use std::io::{self, IsTerminal, Write};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::as_24_bit_terminal_escaped;
+use unicode_width::UnicodeWidthChar;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const STRIKE: &str = "\x1b[9m";
+const CYAN: &str = "\x1b[36m";
+const HEADING: &str = "\x1b[1;35m";
+
+const CODE_THEME: &str = "base16-ocean.dark";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RenderMode {
+    /// Print the answer exactly as received
+    Raw,
+    /// Render markdown with ANSI styles and highlighted code blocks
+    Markdown,
+}
+
+impl RenderMode {
+    /// 未指定时根据 stdout 是否为终端自动选择
+    pub fn resolve(requested: Option<RenderMode>) -> RenderMode {
+        match requested {
+            Some(mode) => mode,
+            None if io::stdout().is_terminal() => RenderMode::Markdown,
+            None => RenderMode::Raw,
+        }
+    }
+}
+
+/// 回答的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// 面向终端的文本输出
+    Text(RenderMode),
+    /// 回答结束后输出一个 JSON 对象
+    Json,
+    /// 每个增量输出一行 JSON 事件
+    Jsonl,
+}
+
+/// 流式输出：按收到的顺序写入片段，根据模式决定是否渲染 markdown
+pub enum Renderer<W: Write> {
+    Raw(W),
+    Markdown(Box<MarkdownRenderer<W>>),
+}
+
+impl<W: Write> Renderer<W> {
+    pub fn new(mode: RenderMode, out: W) -> Self {
+        match mode {
+            RenderMode::Raw => Renderer::Raw(out),
+            RenderMode::Markdown => Renderer::Markdown(Box::new(MarkdownRenderer::new(out))),
+        }
+    }
+
+    pub fn push(&mut self, text: &str) -> io::Result<()> {
+        match self {
+            Renderer::Raw(out) => {
+                out.write_all(text.as_bytes())?;
+                out.flush()
+            }
+            Renderer::Markdown(renderer) => renderer.push(text),
+        }
+    }
+
+    /// 提示信息（暗色单独一行），raw 模式下不输出，保证输出内容和回答完全一致
+    pub fn note(&mut self, text: &str) -> io::Result<()> {
+        match self {
+            Renderer::Raw(_) => Ok(()),
+            Renderer::Markdown(renderer) => renderer.note(text),
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Renderer::Raw(out) => out.flush(),
+            Renderer::Markdown(renderer) => renderer.finish(),
+        }
+    }
+}
+
+struct CodeBlock {
+    fence: String,
+    highlighter: HighlightLines<'static>,
+}
+
+/// 行缓冲的 markdown 渲染器
+///
+/// 普通段落边收边输出；标题、列表、表格、代码块等需要看到整行（或整张表）才能确定格式，
+/// 会等到换行后再渲染。代码块逐行高亮，因此代码块未闭合时也能正常输出。
+pub struct MarkdownRenderer<W: Write> {
+    out: W,
+    /// 当前行中尚未输出的部分
+    line: String,
+    /// 当前行是否已经作为普通段落开始流式输出
+    streaming: bool,
+    inline: InlineState,
+    table: Vec<String>,
+    code: Option<CodeBlock>,
+}
+
+impl<W: Write> MarkdownRenderer<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            line: String::new(),
+            streaming: false,
+            inline: InlineState::default(),
+            table: Vec::new(),
+            code: None,
+        }
+    }
+
+    pub fn push(&mut self, text: &str) -> io::Result<()> {
+        for segment in text.split_inclusive('\n') {
+            match segment.strip_suffix('\n') {
+                Some(rest) => {
+                    self.line.push_str(rest);
+                    self.end_line()?;
+                }
+                None => {
+                    self.line.push_str(segment);
+                    self.stream_partial()?;
+                }
+            }
+        }
+        self.out.flush()
+    }
+
+    /// 在回答中间插入一行提示，不打断正在渲染的段落样式、代码块和还没收完的行
+    pub fn note(&mut self, text: &str) -> io::Result<()> {
+        self.flush_table()?;
+        if self.streaming {
+            // 段落已经输出了一半，提示另起一行，之后的文字在下一行接着用原来的样式
+            writeln!(self.out, "{}", RESET)?;
+        }
+        writeln!(self.out, "{}{}{}", DIM, text, RESET)?;
+        if self.streaming {
+            write!(self.out, "{}", self.inline.style())?;
+        }
+        self.out.flush()
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.streaming || !self.line.is_empty() {
+            self.end_line()?;
+        }
+        self.flush_table()?;
+        if self.code.take().is_some() {
+            write!(self.out, "{}", RESET)?;
+        }
+        self.out.flush()
+    }
+
+    /// 能确定是普通段落时，不等换行直接输出
+    fn stream_partial(&mut self) -> io::Result<()> {
+        if !self.streaming {
+            if self.code.is_some() || !is_paragraph_start(&self.line) {
+                return Ok(());
+            }
+            self.flush_table()?;
+            self.streaming = true;
+        }
+        let text = std::mem::take(&mut self.line);
+        let rendered = self.inline.feed(&text);
+        self.out.write_all(rendered.as_bytes())
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+
+        if self.streaming {
+            self.streaming = false;
+            let mut rendered = self.inline.feed(&line);
+            rendered.push_str(&self.inline.finish());
+            return writeln!(self.out, "{}", rendered);
+        }
+
+        if self.code.is_some() {
+            return self.code_line(&line);
+        }
+
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('|') {
+            self.table.push(line);
+            return Ok(());
+        }
+        self.flush_table()?;
+
+        if let Some(fence) = fence_of(trimmed) {
+            let lang = trimmed[fence.len()..].trim();
+            writeln!(self.out, "{}{}{}", DIM, if lang.is_empty() { "code" } else { lang }, RESET)?;
+            self.code = Some(CodeBlock {
+                fence,
+                highlighter: HighlightLines::new(syntax_for(lang), code_theme()),
+            });
+            return Ok(());
+        }
+
+        let rendered = self.block_line(&line);
+        writeln!(self.out, "{}", rendered)
+    }
+
+    fn code_line(&mut self, line: &str) -> io::Result<()> {
+        let code = self.code.as_mut().expect("code block is open");
+        let trimmed = line.trim();
+        // 闭合的 fence 可以比开头的长，但不能更短，也不能带语言标记
+        if fence_of(trimmed).is_some_and(|fence| fence == trimmed && fence.starts_with(code.fence.as_str())) {
+            self.code = None;
+            return writeln!(self.out, "{}{}{}", DIM, "─".repeat(4), RESET);
+        }
+
+        let source = format!("{}\n", line);
+        match code.highlighter.highlight_line(&source, syntax_set()) {
+            Ok(ranges) => {
+                let escaped = as_24_bit_terminal_escaped(&ranges, false);
+                writeln!(self.out, "{}{}", escaped.trim_end_matches('\n'), RESET)
+            }
+            Err(_) => writeln!(self.out, "{}", line),
+        }
+    }
+
+    fn block_line(&mut self, line: &str) -> String {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+
+        let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            let title = self.inline_line(trimmed[hashes..].trim());
+            return format!("{}{}{}", HEADING, title, RESET);
+        }
+
+        if is_rule(trimmed) {
+            return format!("{}{}{}", DIM, "─".repeat(40), RESET);
+        }
+
+        if let Some(rest) = trimmed.strip_prefix("> ").or_else(|| trimmed.strip_prefix('>')) {
+            return format!("{}│{} {}{}{}", DIM, RESET, ITALIC, self.inline_line(rest), RESET);
+        }
+
+        for marker in ["- ", "* ", "+ "] {
+            if let Some(rest) = trimmed.strip_prefix(marker) {
+                let rest = match rest.strip_prefix("[ ] ") {
+                    Some(task) => format!("☐ {}", task),
+                    None => match rest.strip_prefix("[x] ").or_else(|| rest.strip_prefix("[X] ")) {
+                        Some(task) => format!("☑ {}", task),
+                        None => rest.to_string(),
+                    },
+                };
+                return format!("{}{}•{} {}", indent, CYAN, RESET, self.inline_line(&rest));
+            }
+        }
+
+        let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits > 0 {
+            let rest = &trimmed[digits..];
+            if rest.starts_with(". ") || rest.starts_with(") ") {
+                let marker = &trimmed[..digits + 1];
+                return format!("{}{}{}{} {}", indent, CYAN, marker, RESET, self.inline_line(&rest[2..]));
+            }
+        }
+
+        self.inline_line(line)
+    }
+
+    fn inline_line(&mut self, text: &str) -> String {
+        let mut rendered = self.inline.feed(text);
+        rendered.push_str(&self.inline.finish());
+        rendered
+    }
+
+    fn flush_table(&mut self) -> io::Result<()> {
+        if self.table.is_empty() {
+            return Ok(());
+        }
+        let lines = std::mem::take(&mut self.table);
+
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut header_rows = 0;
+        for line in &lines {
+            let cells = split_row(line);
+            if cells.iter().all(|cell| is_separator_cell(cell)) {
+                header_rows = rows.len();
+                continue;
+            }
+            rows.push(cells.iter().map(|cell| self.inline_line(cell)).collect());
+        }
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        for row in &rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(visible_width(cell));
+            }
+        }
+
+        for (i, row) in rows.iter().enumerate() {
+            let mut line = String::new();
+            for (j, width) in widths.iter().enumerate() {
+                let cell = row.get(j).map(String::as_str).unwrap_or("");
+                let padding = " ".repeat(width - visible_width(cell));
+                if i < header_rows {
+                    line.push_str(&format!("{}│{} {}{}{}{} ", DIM, RESET, BOLD, cell, RESET, padding));
+                } else {
+                    line.push_str(&format!("{}│{} {}{} ", DIM, RESET, cell, padding));
+                }
+            }
+            writeln!(self.out, "{}{}│{}", line, DIM, RESET)?;
+            if i + 1 == header_rows {
+                let rule: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+                writeln!(self.out, "{}├{}┤{}", DIM, rule.join("┼"), RESET)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 行内样式状态：粗体、斜体、删除线、行内代码
+///
+/// 片段末尾的 `*`、`~` 可能是双字符标记的前半部分，先保留到下一个片段再处理。
+/// 下划线不作为强调标记，`__init__`、`files__read_file` 这类标识符原样输出。
+#[derive(Default)]
+struct InlineState {
+    bold: bool,
+    italic: bool,
+    strike: bool,
+    code: bool,
+    held: String,
+}
+
+impl InlineState {
+    fn feed(&mut self, text: &str) -> String {
+        let mut input = std::mem::take(&mut self.held);
+        input.push_str(text);
+
+        let chars: Vec<char> = input.chars().collect();
+        let mut out = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+
+            if c == '`' {
+                self.code = !self.code;
+                out.push_str(&self.style());
+                i += 1;
+                continue;
+            }
+            if self.code {
+                out.push(c);
+                i += 1;
+                continue;
+            }
+            if matches!(c, '*' | '~') && next.is_none() {
+                self.held.push(c);
+                break;
+            }
+            match (c, next) {
+                ('*', Some('*')) => {
+                    self.bold = !self.bold;
+                    out.push_str(&self.style());
+                    i += 2;
+                }
+                ('~', Some('~')) => {
+                    self.strike = !self.strike;
+                    out.push_str(&self.style());
+                    i += 2;
+                }
+                ('*', Some(n)) if self.italic || !n.is_whitespace() => {
+                    self.italic = !self.italic;
+                    out.push_str(&self.style());
+                    i += 1;
+                }
+                _ => {
+                    out.push(c);
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+
+    /// 行结束：输出保留的字符并清除所有样式
+    fn finish(&mut self) -> String {
+        let held = std::mem::take(&mut self.held);
+        let mut out = if held == "*" && self.italic {
+            String::new()
+        } else {
+            held
+        };
+        *self = InlineState::default();
+        out.push_str(RESET);
+        out
+    }
+
+    fn style(&self) -> String {
+        let mut style = String::from(RESET);
+        if self.bold {
+            style.push_str(BOLD);
+        }
+        if self.italic {
+            style.push_str(ITALIC);
+        }
+        if self.strike {
+            style.push_str(STRIKE);
+        }
+        if self.code {
+            style.push_str(CYAN);
+        }
+        style
+    }
+}
+
+fn is_paragraph_start(line: &str) -> bool {
+    match line.trim_start().chars().next() {
+        None => false,
+        Some(c) => !c.is_ascii_digit() && !"#|`~>-*_+=".contains(c),
+    }
+}
+
+fn fence_of(line: &str) -> Option<String> {
+    for marker in ['`', '~'] {
+        let count = line.chars().take_while(|c| *c == marker).count();
+        if count >= 3 {
+            return Some(marker.to_string().repeat(count));
+        }
+    }
+    None
+}
+
+fn is_rule(line: &str) -> bool {
+    let line = line.trim_end();
+    line.len() >= 3
+        && ["-", "*", "_"]
+            .iter()
+            .any(|m| line.chars().all(|c| c.to_string() == *m || c == ' '))
+}
+
+fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|').map(|cell| cell.trim().to_string()).collect()
+}
+
+fn is_separator_cell(cell: &str) -> bool {
+    let cell = cell.trim();
+    !cell.is_empty() && cell.contains('-') && cell.chars().all(|c| c == '-' || c == ':')
+}
+
+/// 终端中的显示宽度，忽略 ANSI 转义序列
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        width += c.width().unwrap_or(0);
+    }
+    width
+}
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: std::sync::OnceLock<SyntaxSet> = std::sync::OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn code_theme() -> &'static Theme {
+    static THEME: std::sync::OnceLock<Theme> = std::sync::OnceLock::new();
+    THEME.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults();
+        themes.themes.remove(CODE_THEME).expect("default theme exists")
+    })
+}
+
+fn syntax_for(lang: &str) -> &'static syntect::parsing::SyntaxReference {
+    let syntaxes = syntax_set();
+    let token = lang.split_whitespace().next().unwrap_or("");
+    syntaxes
+        .find_syntax_by_token(token)
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(chunks: &[&str]) -> String {
+        let mut out = Vec::new();
+        let mut renderer = MarkdownRenderer::new(&mut out);
+        for chunk in chunks {
+            renderer.push(chunk).unwrap();
+        }
+        renderer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// 去掉 ANSI 转义序列，只留下文字
+    fn plain(text: &str) -> String {
+        let mut plain = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| c.is_ascii_alphabetic());
+            } else {
+                plain.push(c);
+            }
+        }
+        plain
+    }
+
+    #[test]
+    fn raw_mode_passes_text_through() {
+        let mut out = Vec::new();
+        let mut renderer = Renderer::new(RenderMode::Raw, &mut out);
+        renderer.push("# Title\n**bold**").unwrap();
+        renderer.note("hidden").unwrap();
+        renderer.finish().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "# Title\n**bold**");
+    }
+
+    #[test]
+    fn styles_split_across_chunks() {
+        let out = render(&["Some **bo", "ld*", "* and `co", "de` here\n"]);
+        assert!(out.contains(&format!("{}{}bold{}", RESET, BOLD, RESET)), "{:?}", out);
+        assert!(out.contains(&format!("{}{}code{}", RESET, CYAN, RESET)), "{:?}", out);
+        assert_eq!(plain(&out), "Some bold and code here\n");
+    }
+
+    #[test]
+    fn underscores_are_left_alone() {
+        let out = render(&["Call `obj`.__init__ or files__read_", "file, not __bold__\n"]);
+        assert!(!out.contains(BOLD), "{:?}", out);
+        assert_eq!(plain(&out), "Call obj.__init__ or files__read_file, not __bold__\n");
+    }
+
+    #[test]
+    fn headings_lists_and_rules() {
+        let out = plain(&render(&["## Title\n- item\n2. second\n- [x] done\n---\n"]));
+        assert_eq!(out, format!("Title\n• item\n2. second\n• ☑ done\n{}\n", "─".repeat(40)));
+    }
+
+    #[test]
+    fn ordered_lists_keep_their_delimiter() {
+        let out = plain(&render(&["1) first
+", "  10. tenth
+3)no space
+"]));
+        assert_eq!(out, "1) first
+  10. tenth
+3)no space
+");
+    }
+
+    #[test]
+    fn notes_keep_an_open_code_block() {
+        let mut out = Vec::new();
+        let mut renderer = Renderer::new(RenderMode::Markdown, &mut out);
+        renderer.push("```rust\nlet x = 1;\nlet y").unwrap();
+        renderer.note("⚙ read_file").unwrap();
+        renderer.push(" = 2;\n```\n# after\n").unwrap();
+        renderer.finish().unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // 代码块没有被提前关闭，闭合的 fence 之后的标题照常渲染
+        assert_eq!(plain(&out), "rust\nlet x = 1;\n⚙ read_file\nlet y = 2;\n────\nafter\n");
+        assert!(out.contains(&format!("{}after{}", HEADING, RESET)), "{:?}", out);
+    }
+
+    #[test]
+    fn notes_resume_paragraph_styles() {
+        let mut out = Vec::new();
+        let mut renderer = Renderer::new(RenderMode::Markdown, &mut out);
+        renderer.push("Some **bold").unwrap();
+        renderer.note("Thinking…").unwrap();
+        renderer.push(" text** done\n").unwrap();
+        renderer.finish().unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(plain(&out), "Some bold\nThinking…\n text done\n");
+        assert!(out.contains(&format!("{}\n{}Thinking…{}\n{}{} text", RESET, DIM, RESET, RESET, BOLD)), "{:?}", out);
+    }
+
+    #[test]
+    fn tables_are_aligned() {
+        let out = plain(&render(&["| a | long header |\n|---|---|\n| wide cell | x |\n\nafter\n"]));
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "│ a         │ long header │");
+        assert_eq!(lines[2], "│ wide cell │ x           │");
+        assert!(out.ends_with("\nafter\n"));
+    }
+
+    #[test]
+    fn code_blocks_close_on_a_longer_fence() {
+        let out = render(&["```rust\nlet x = 1;\n````\nafter **bold**\n"]);
+        assert!(out.contains(&format!("{}{}bold", RESET, BOLD)), "{:?}", out);
+        assert!(plain(&out).starts_with("rust\nlet x = 1;\n────\nafter bold"));
+    }
+
+    #[test]
+    fn code_blocks_ignore_shorter_or_different_fences() {
+        let out = plain(&render(&["````md\n```\n~~~~\n``` not a fence\n````\ndone\n"]));
+        assert_eq!(out, "md\n```\n~~~~\n``` not a fence\n────\ndone\n");
+    }
+
+    #[test]
+    fn unclosed_code_block_is_reset_at_the_end() {
+        let out = render(&["```\nunfinished"]);
+        assert!(out.ends_with(RESET));
+        assert_eq!(plain(&out), "code\nunfinished\n");
+    }
+}
@@ -0,0 +1,472 @@
+use crate::bridge::{Bridge, Usage};
+use crate::config::Config;
+use crate::usage;
+use anyhow::{bail, Context, Result};
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// 代理运行中产生的事件，由调用方决定如何展示
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    Listening { address: SocketAddr },
+    /// 一个请求处理完毕；`usage` 只有上游返回了用量时才有
+    Request {
+        method: &'static str,
+        path: &'static str,
+        model: Option<String>,
+        stream: bool,
+        status: u16,
+        usage: Option<Usage>,
+        elapsed_ms: u128,
+    },
+    /// 不影响响应的问题，如预算提醒、用量写入失败、监听了非本机地址
+    Warning { message: String },
+    /// 请求失败的原因，响应体中也会带上
+    Error { message: String },
+}
+
+/// 接收 `ServerEvent` 的回调，会在多个请求中同时调用
+pub type OnEvent = Arc<dyn Fn(&ServerEvent) + Send + Sync>;
+
+/// 代理共享的状态：配置，以及按服务商地址缓存的 `Bridge`（避免每个请求都执行 `token_command`）
+struct Proxy {
+    config: Config,
+    bridges: Mutex<HashMap<String, Arc<Bridge>>>,
+    on_event: OnEvent,
+}
+
+/// 一个请求实际使用的模型和服务商
+struct Target {
+    /// 解析了 `model` 的配置，用于计价和记录用量
+    config: Config,
+    bridge: Arc<Bridge>,
+}
+
+impl Proxy {
+    /// 和 `Core` 一样解析模型别名和 `[providers]`
+    fn target(&self, model: Option<&str>) -> Result<Target> {
+        let mut config = self.config.clone();
+        if let Some(model) = model {
+            config.override_model(model);
+        }
+        let api_base = config.effective_api_base().to_string();
+        let mut bridges = self.bridges.lock().unwrap_or_else(|e| e.into_inner());
+        let bridge = match bridges.get(&api_base) {
+            Some(bridge) => bridge.clone(),
+            None => {
+                let (api_base, token) = config.connection()?;
+                let bridge = Arc::new(Bridge::new(api_base.clone(), token, config.model_id().to_string()));
+                bridges.insert(api_base, bridge.clone());
+                bridge
+            }
+        };
+        Ok(Target { config, bridge })
+    }
+
+    /// 报告错误并生成 OpenAI 格式的错误响应
+    fn fail(&self, status: StatusCode, kind: &str, e: anyhow::Error) -> Response {
+        (self.on_event)(&ServerEvent::Error {
+            message: format!("{:#}", e),
+        });
+        error_response(status, kind, e)
+    }
+}
+
+/// 本地 OpenAI 兼容代理：请求经由配置的 `Bridge` 转发到上游，客户端无需持有 token
+///
+/// 和 `chat` 一样解析模型别名、检查预算并记录用量。代理不做认证，
+/// 只有 `allow_remote` 时才允许监听非本机地址。
+pub async fn serve(config: &Config, host: &str, port: u16, allow_remote: bool, on_event: OnEvent) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind((host, port))
+        .await
+        .with_context(|| format!("Failed to bind {}:{}", host, port))?;
+    let address = listener.local_addr()?;
+    if !address.ip().is_loopback() {
+        if !allow_remote {
+            bail!(
+                "Refusing to listen on {}: the proxy has no authentication and sends your token upstream for anyone who can reach it. Pass --allow-remote to do it anyway",
+                address.ip()
+            );
+        }
+        on_event(&ServerEvent::Warning {
+            message: format!(
+                "Listening on {} without authentication; anyone who can reach it can use your token",
+                address.ip()
+            ),
+        });
+    }
+    on_event(&ServerEvent::Listening { address });
+
+    let app = router(config.clone(), on_event);
+    axum::serve(listener, app).await.context("Server error")?;
+    Ok(())
+}
+
+fn router(config: Config, on_event: OnEvent) -> Router {
+    let proxy = Arc::new(Proxy {
+        config,
+        bridges: Mutex::new(HashMap::new()),
+        on_event,
+    });
+    Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/models", get(models))
+        .with_state(proxy)
+}
+
+async fn chat_completions(
+    State(proxy): State<Arc<Proxy>>,
+    Json(mut body): Json<serde_json::Value>,
+) -> Response {
+    let start = Instant::now();
+    let target = match proxy.target(body["model"].as_str()) {
+        Ok(target) => target,
+        Err(e) => return proxy.fail(StatusCode::BAD_REQUEST, "invalid_request_error", e),
+    };
+    let records = match usage::load(&target.config.usage_path()) {
+        Ok(records) => records,
+        Err(e) => return proxy.fail(StatusCode::INTERNAL_SERVER_ERROR, "server_error", e),
+    };
+    match usage::check_budget(&target.config.budget, &records) {
+        Ok(Some(message)) => (proxy.on_event)(&ServerEvent::Warning { message }),
+        Ok(None) => {}
+        Err(e) => return proxy.fail(StatusCode::TOO_MANY_REQUESTS, "budget_exceeded", e),
+    }
+
+    let model = target.config.model_id().to_string();
+    let stream = body["stream"].as_bool().unwrap_or(false);
+    if let Some(map) = body.as_object_mut() {
+        map.insert("model".to_string(), model.clone().into());
+        // 流式响应默认不带用量，需要用量才能记账和检查预算
+        if stream && target.config.stream_usage && !map.contains_key("stream_options") {
+            map.insert("stream_options".to_string(), serde_json::json!({ "include_usage": true }));
+        }
+    }
+
+    let response = match target.bridge.forward_chat(body).await {
+        Ok(response) => response,
+        Err(e) => return proxy.fail(StatusCode::BAD_GATEWAY, "upstream_error", e),
+    };
+    let status = response.status();
+    let events = proxy.clone();
+    let log = move |usage: Option<Usage>| {
+        if let Some(usage) = &usage {
+            let record = usage::Record::new(&target.config, &model, usage);
+            if let Err(e) = usage::append(&target.config.usage_path(), &record) {
+                (events.on_event)(&ServerEvent::Warning {
+                    message: format!("Failed to record usage: {:#}", e),
+                });
+            }
+        }
+        (events.on_event)(&ServerEvent::Request {
+            method: "POST",
+            path: "/v1/chat/completions",
+            model: Some(model.clone()),
+            stream,
+            status: status.as_u16(),
+            usage,
+            elapsed_ms: start.elapsed().as_millis(),
+        });
+    };
+
+    if !status.is_success() {
+        log(None);
+        return upstream_status(&proxy, response).await;
+    }
+    if !stream {
+        let content_type = content_type(&response);
+        let bytes = match response.bytes().await {
+            Ok(bytes) => bytes,
+            Err(e) => return proxy.fail(StatusCode::BAD_GATEWAY, "upstream_error", e.into()),
+        };
+        let usage = serde_json::from_slice::<serde_json::Value>(&bytes)
+            .ok()
+            .and_then(|value| serde_json::from_value::<Usage>(value["usage"].clone()).ok());
+        log(usage);
+        return respond(StatusCode::OK, content_type, Body::from(bytes));
+    }
+
+    // 流式响应边转发边找出用量，结束时记账
+    let content_type = content_type(&response);
+    let tap = Arc::new(Mutex::new(UsageTap::default()));
+    let feed = tap.clone();
+    let body = response
+        .bytes_stream()
+        .map(move |chunk| {
+            if let Ok(bytes) = &chunk {
+                feed.lock().unwrap_or_else(|e| e.into_inner()).feed(bytes);
+            }
+            chunk
+        })
+        .chain(
+            futures::stream::once(async move {
+                let mut tap = tap.lock().unwrap_or_else(|e| e.into_inner());
+                // 最后一行（常常就是 usage）可能没有换行
+                tap.feed(b"\n");
+                let usage = tap.usage.take();
+                log(usage);
+            })
+            .filter_map(|()| async { None::<reqwest::Result<Bytes>> }),
+        );
+    respond(StatusCode::OK, content_type, Body::from_stream(body))
+}
+
+async fn models(State(proxy): State<Arc<Proxy>>) -> Response {
+    let start = Instant::now();
+    let target = match proxy.target(None) {
+        Ok(target) => target,
+        Err(e) => return proxy.fail(StatusCode::BAD_REQUEST, "invalid_request_error", e),
+    };
+    let response = match target.bridge.forward_get("models").await {
+        Ok(response) => response,
+        Err(e) => return proxy.fail(StatusCode::BAD_GATEWAY, "upstream_error", e),
+    };
+    (proxy.on_event)(&ServerEvent::Request {
+        method: "GET",
+        path: "/v1/models",
+        model: None,
+        stream: false,
+        status: response.status().as_u16(),
+        usage: None,
+        elapsed_ms: start.elapsed().as_millis(),
+    });
+    if !response.status().is_success() {
+        return upstream_status(&proxy, response).await;
+    }
+    let content_type = content_type(&response);
+    respond(StatusCode::OK, content_type, Body::from_stream(response.bytes_stream()))
+}
+
+/// 在 SSE 流中找出带 `usage` 的那个块
+#[derive(Default)]
+struct UsageTap {
+    /// 还没有遇到换行的部分
+    buffer: String,
+    usage: Option<Usage>,
+}
+
+impl UsageTap {
+    fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.push_str(&String::from_utf8_lossy(bytes));
+        while let Some(end) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=end).collect();
+            let Some(data) = line.trim().strip_prefix("data:") else {
+                continue;
+            };
+            if let Ok(chunk) = serde_json::from_str::<serde_json::Value>(data.trim()) {
+                if let Ok(usage) = serde_json::from_value::<Usage>(chunk["usage"].clone()) {
+                    self.usage = Some(usage);
+                }
+            }
+        }
+    }
+}
+
+fn content_type(response: &reqwest::Response) -> String {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/json")
+        .to_string()
+}
+
+fn respond(status: StatusCode, content_type: String, body: Body) -> Response {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap_or_else(|e| error_response(StatusCode::BAD_GATEWAY, "upstream_error", e.into()))
+}
+
+/// 上游返回错误状态时保留状态码；响应体不是 OpenAI 格式的错误时包装成 `{"error": {...}}`
+async fn upstream_status(proxy: &Proxy, upstream: reqwest::Response) -> Response {
+    let status = StatusCode::from_u16(upstream.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let text = upstream.text().await.unwrap_or_default();
+    let body = match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(value) if value.get("error").is_some() => value,
+        _ => {
+            let message = if text.trim().is_empty() {
+                status.canonical_reason().unwrap_or("Upstream error").to_string()
+            } else {
+                text.trim().to_string()
+            };
+            serde_json::json!({
+                "error": {
+                    "message": message,
+                    "type": "upstream_error",
+                    "code": status.as_u16(),
+                }
+            })
+        }
+    };
+    (proxy.on_event)(&ServerEvent::Error {
+        message: format!(
+            "Upstream returned {}: {}",
+            status.as_u16(),
+            body["error"]["message"].as_str().unwrap_or_default()
+        ),
+    });
+    (status, Json(body)).into_response()
+}
+
+fn error_response(status: StatusCode, kind: &str, e: anyhow::Error) -> Response {
+    let body = serde_json::json!({
+        "error": {
+            "message": format!("{:#}", e),
+            "type": kind,
+        }
+    });
+    (status, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// 假的上游：记下收到的模型，`broken` 模型返回纯文本的 500
+    async fn stub_upstream(seen: Arc<Mutex<Vec<String>>>) -> String {
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(move |Json(body): Json<serde_json::Value>| {
+                let seen = seen.clone();
+                async move {
+                    let model = body["model"].as_str().unwrap_or_default().to_string();
+                    seen.lock().unwrap().push(model.clone());
+                    if model == "broken" {
+                        return (StatusCode::INTERNAL_SERVER_ERROR, "boom").into_response();
+                    }
+                    Json(serde_json::json!({
+                        "model": model,
+                        "choices": [{ "message": { "role": "assistant", "content": "hi" }, "finish_reason": "stop" }],
+                        "usage": { "prompt_tokens": 7, "completion_tokens": 3, "total_tokens": 10 },
+                    }))
+                    .into_response()
+                }
+            }),
+        );
+        spawn(app).await
+    }
+
+    async fn spawn(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", address)
+    }
+
+    fn config(dir: &std::path::Path, upstream: &str, extra: &str) -> Config {
+        let path = dir.join("config.toml");
+        fs::write(
+            &path,
+            format!(
+                "api_base = \"{}/v1/chat/completions\"\ntoken = \"sk-test\"\nmodel = \"default-model\"\n{}",
+                upstream, extra
+            ),
+        )
+        .unwrap();
+        Config::load_isolated(&path, &dir.join("history.toml")).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat-server-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 收集代理产生的事件
+    fn recorder() -> (OnEvent, Arc<Mutex<Vec<ServerEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        (Arc::new(move |event: &ServerEvent| sink.lock().unwrap().push(event.clone())), events)
+    }
+
+    async fn post_chat(proxy: &str, model: &str) -> (u16, serde_json::Value) {
+        let response = reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", proxy))
+            .json(&serde_json::json!({ "model": model, "messages": [{ "role": "user", "content": "hello" }] }))
+            .send()
+            .await
+            .unwrap();
+        (response.status().as_u16(), response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn resolves_aliases_and_records_usage() {
+        let dir = temp_dir("alias");
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let upstream = stub_upstream(seen.clone()).await;
+        let config = config(&dir, &upstream, "[models.fast]\nid = \"real-model\"\n");
+        let (on_event, events) = recorder();
+        let proxy = spawn(router(config.clone(), on_event)).await;
+
+        let (status, body) = post_chat(&proxy, "fast").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["model"], "real-model");
+        assert_eq!(*seen.lock().unwrap(), ["real-model"]);
+
+        let records = usage::load(&config.usage_path()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].model, "real-model");
+        assert_eq!(records[0].prompt_tokens, 7);
+
+        let events = events.lock().unwrap();
+        assert!(matches!(
+            events.as_slice(),
+            [ServerEvent::Request { method: "POST", model: Some(model), status: 200, usage: Some(usage), .. }]
+                if model == "real-model" && usage.completion_tokens == 3
+        ));
+    }
+
+    #[tokio::test]
+    async fn wraps_upstream_errors() {
+        let dir = temp_dir("error");
+        let upstream = stub_upstream(Arc::new(Mutex::new(Vec::new()))).await;
+        let (on_event, events) = recorder();
+        let proxy = spawn(router(config(&dir, &upstream, ""), on_event)).await;
+
+        let (status, body) = post_chat(&proxy, "broken").await;
+        assert_eq!(status, 500);
+        assert_eq!(body["error"]["message"], "boom");
+        assert_eq!(body["error"]["type"], "upstream_error");
+        assert!(events.lock().unwrap().iter().any(
+            |event| matches!(event, ServerEvent::Error { message } if message == "Upstream returned 500: boom")
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_over_budget() {
+        let dir = temp_dir("budget");
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let upstream = stub_upstream(seen.clone()).await;
+        let (on_event, _) = recorder();
+        let proxy = spawn(router(config(&dir, &upstream, "[budget]\ndaily_limit = 0.0\n"), on_event)).await;
+
+        let (status, body) = post_chat(&proxy, "default-model").await;
+        assert_eq!(status, 429);
+        assert_eq!(body["error"]["type"], "budget_exceeded");
+        assert!(seen.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refuses_remote_hosts_without_opt_in() {
+        let dir = temp_dir("remote");
+        let config = config(&dir, "http://127.0.0.1:9", "");
+        let (on_event, events) = recorder();
+        let error = serve(&config, "0.0.0.0", 0, false, on_event).await.unwrap_err();
+        assert!(error.to_string().contains("--allow-remote"), "{}", error);
+        assert!(events.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+use crate::bridge::{JsonSchemaFormat, ResponseFormat};
+use anyhow::{anyhow, Context, Result};
+use jsonschema::Validator;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// 要求回答是 JSON 时的约束
+pub enum StructuredOutput {
+    /// 任意 JSON 对象
+    Object,
+    /// 符合给定 JSON Schema 的 JSON
+    Schema {
+        name: String,
+        schema: Value,
+        validator: Box<Validator>,
+        /// 请服务商严格按 schema 生成；OpenAI 要求每个对象都有 `additionalProperties: false` 且所有属性都在 `required` 中
+        strict: bool,
+    },
+}
+
+impl StructuredOutput {
+    /// 从文件读取 JSON Schema，文件名（不含扩展名）作为 schema 的名字
+    pub fn from_schema_file(path: &Path, strict: bool) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let schema: Value = serde_json::from_str(&content)
+            .with_context(|| format!("{} is not valid JSON", path.display()))?;
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|e| anyhow!("{} is not a valid JSON Schema: {}", path.display(), e))?;
+        // 服务商要求名字只含字母、数字、`_` 和 `-`
+        let name: String = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .take(64)
+            .collect();
+        Ok(Self::Schema {
+            name: if name.is_empty() { "response".to_string() } else { name },
+            schema,
+            validator: Box::new(validator),
+            strict,
+        })
+    }
+
+    pub fn response_format(&self) -> ResponseFormat {
+        match self {
+            Self::Object => ResponseFormat::JsonObject,
+            Self::Schema { name, schema, strict, .. } => ResponseFormat::JsonSchema {
+                json_schema: JsonSchemaFormat {
+                    name: name.clone(),
+                    schema: schema.clone(),
+                    strict: *strict,
+                },
+            },
+        }
+    }
+
+    /// 加在系统提示后的说明，不支持 `response_format` 的服务商只能靠它
+    pub fn instruction(&self) -> String {
+        match self {
+            Self::Object => "Reply with a single JSON object and nothing else: no explanations, no Markdown code fences.".to_string(),
+            Self::Schema { schema, .. } => format!(
+                "Reply with a single JSON value that conforms to the following JSON Schema, and nothing else: no explanations, no Markdown code fences.\n\n{}",
+                serde_json::to_string_pretty(schema).unwrap_or_default()
+            ),
+        }
+    }
+
+    /// 检查回答，成功时返回其中的 JSON 文本，失败时返回问题描述
+    pub fn check(&self, answer: &str) -> Result<String, String> {
+        let text = strip_code_fence(answer);
+        let value: Value = serde_json::from_str(text).map_err(|e| format!("the reply is not valid JSON ({})", e))?;
+        match self {
+            Self::Object if !value.is_object() => Err("the reply is not a JSON object".to_string()),
+            Self::Object => Ok(text.to_string()),
+            Self::Schema { validator, .. } => {
+                let errors: Vec<String> = validator
+                    .iter_errors(&value)
+                    .take(5)
+                    .map(|error| {
+                        let path = error.instance_path.to_string();
+                        if path.is_empty() {
+                            error.to_string()
+                        } else {
+                            format!("{}: {}", path, error)
+                        }
+                    })
+                    .collect();
+                if errors.is_empty() {
+                    Ok(text.to_string())
+                } else {
+                    Err(format!("the reply does not match the schema ({})", errors.join("; ")))
+                }
+            }
+        }
+    }
+}
+
+/// 模型常把 JSON 包在 ```json 代码块里，去掉外层的代码块标记
+fn strip_code_fence(answer: &str) -> &str {
+    let text = answer.trim();
+    let Some(rest) = text.strip_prefix("```") else {
+        return text;
+    };
+    let Some(body) = rest.strip_suffix("```") else {
+        return text;
+    };
+    // 去掉语言标记所在的第一行
+    body.split_once('\n').map_or(body, |(_, code)| code).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema(name: &str, strict: bool) -> StructuredOutput {
+        let path = std::env::temp_dir().join(format!("chat-structured-test-{}-{}.schema.json", name, std::process::id()));
+        let schema = json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" }, "age": { "type": "integer", "minimum": 0 } },
+            "required": ["name", "age"],
+            "additionalProperties": false,
+        });
+        fs::write(&path, schema.to_string()).unwrap();
+        let structured = StructuredOutput::from_schema_file(&path, strict).unwrap();
+        fs::remove_file(&path).unwrap();
+        structured
+    }
+
+    #[test]
+    fn strips_code_fences() {
+        assert_eq!(strip_code_fence("```json\n{\"a\": 1}\n```"), "{\"a\": 1}");
+        assert_eq!(strip_code_fence("  ```\n[1]\n```\n"), "[1]");
+        assert_eq!(strip_code_fence("{\"a\": \"```\"}"), "{\"a\": \"```\"}");
+        assert_eq!(strip_code_fence("```json\n{}"), "```json\n{}");
+        let answer = StructuredOutput::Object.check("```json\n{\"ok\": true}\n```").unwrap();
+        assert_eq!(answer, "{\"ok\": true}");
+    }
+
+    #[test]
+    fn json_object_rejects_other_values() {
+        let object = StructuredOutput::Object;
+        assert!(object.check("{}").is_ok());
+        assert_eq!(object.check("[1, 2]").unwrap_err(), "the reply is not a JSON object");
+        assert!(object.check("Sure! {\"a\": 1}").unwrap_err().starts_with("the reply is not valid JSON"));
+    }
+
+    #[test]
+    fn schema_violations_are_reported() {
+        let schema = schema("check", false);
+        assert!(schema.check("{\"name\": \"Ada\", \"age\": 36}").is_ok());
+        let error = schema.check("{\"name\": \"Ada\", \"age\": -1}").unwrap_err();
+        assert!(error.starts_with("the reply does not match the schema") && error.contains("/age"), "{}", error);
+        let error = schema.check("{\"name\": \"Ada\"}").unwrap_err();
+        assert!(error.contains("age"), "{}", error);
+    }
+
+    #[test]
+    fn response_format_is_strict_only_on_request() {
+        let format = |structured: &StructuredOutput| serde_json::to_value(structured.response_format()).unwrap();
+        assert_eq!(format(&StructuredOutput::Object), json!({ "type": "json_object" }));
+        let loose = format(&schema("loose", false));
+        assert_eq!(loose["type"], "json_schema");
+        assert_eq!(loose["json_schema"]["strict"], false);
+        assert_eq!(loose["json_schema"]["schema"]["required"], json!(["name", "age"]));
+        assert_eq!(format(&schema("strict", true))["json_schema"]["strict"], true);
+        // schema 的名字取自文件名，不允许的字符换成 `_`
+        assert!(loose["json_schema"]["name"].as_str().unwrap().starts_with("chat-structured-test-loose-"));
+        assert!(loose["json_schema"]["name"].as_str().unwrap().ends_with("_schema"));
+    }
+
+    #[test]
+    fn instruction_describes_the_format() {
+        assert!(StructuredOutput::Object.instruction().contains("single JSON object"));
+        let instruction = schema("instruction", false).instruction();
+        assert!(instruction.contains("JSON Schema") && instruction.contains("\"additionalProperties\": false"));
+    }
+}
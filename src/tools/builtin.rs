@@ -0,0 +1,371 @@
+use super::{command_output, Tool, ToolRegistry};
+use crate::config::BuiltinTool;
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use walkdir::WalkDir;
+
+/// 读取、搜索时跳过的大文件
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// `search_text` 最多返回的匹配行数
+const MAX_MATCHES: usize = 200;
+
+/// 注册启用的内置工具，它们都只能访问 `root`（当前目录）之下的文件
+pub fn register(registry: &mut ToolRegistry, tools: &[BuiltinTool], root: &Path, allowed_commands: &[String]) {
+    let root = root.to_path_buf();
+    for tool in tools {
+        match tool {
+            BuiltinTool::ReadFile => registry.register(ReadFile { root: root.clone() }),
+            BuiltinTool::ListDir => registry.register(ListDir { root: root.clone() }),
+            BuiltinTool::SearchText => registry.register(SearchText { root: root.clone() }),
+            BuiltinTool::WriteFile => registry.register(WriteFile { root: root.clone() }),
+            BuiltinTool::RunCommand => registry.register(RunCommand {
+                root: root.clone(),
+                allowed: allowed_commands.to_vec(),
+            }),
+        }
+    }
+}
+
+/// 把模型给出的路径解析到 `root` 之下，拒绝跳出 `root` 的路径（包括经由符号链接）
+fn resolve(root: &Path, path: &str) -> Result<PathBuf> {
+    let joined = root.join(path);
+    // 目标可能还不存在（write_file），规范化最近的已存在祖先，再接上其余部分；
+    // 悬空的符号链接也算存在，否则写入时会顺着它写到 `root` 之外
+    let mut existing = joined.as_path();
+    let mut rest = Vec::new();
+    while fs::symlink_metadata(existing).is_err() {
+        match existing.components().next_back() {
+            Some(Component::Normal(name)) => rest.push(name),
+            _ => bail!("Invalid path `{}`", path),
+        }
+        existing = existing.parent().with_context(|| format!("Invalid path `{}`", path))?;
+    }
+    let mut resolved = existing
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve `{}`", path))?;
+    resolved.extend(rest.into_iter().rev());
+
+    if !resolved.starts_with(root) {
+        bail!("`{}` is outside the working directory", path);
+    }
+    Ok(resolved)
+}
+
+fn string_arg<'a>(arguments: &'a Value, name: &str) -> Result<&'a str> {
+    arguments[name]
+        .as_str()
+        .with_context(|| format!("Missing string argument `{}`", name))
+}
+
+/// 相对 `root` 的显示路径
+fn display(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    if relative.as_os_str().is_empty() {
+        ".".to_string()
+    } else {
+        relative.display().to_string()
+    }
+}
+
+struct ReadFile {
+    root: PathBuf,
+}
+
+impl Tool for ReadFile {
+    fn name(&self) -> &str {
+        "read_file"
+    }
+
+    fn description(&self) -> &str {
+        "Read a text file in the current working directory, optionally only a range of lines (1-based, inclusive)."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "Path relative to the working directory" },
+                "start_line": { "type": "integer" },
+                "end_line": { "type": "integer" },
+            },
+            "required": ["path"],
+        })
+    }
+
+    fn needs_confirmation(&self) -> bool {
+        false
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String> {
+        let path = resolve(&self.root, string_arg(arguments, "path")?)?;
+        if fs::metadata(&path)?.len() > MAX_FILE_SIZE {
+            bail!("File is larger than {} bytes; read a range of lines instead", MAX_FILE_SIZE);
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", display(&self.root, &path)))?;
+
+        let start = arguments["start_line"].as_u64().unwrap_or(1).max(1) as usize;
+        let end = arguments["end_line"].as_u64().map_or(usize::MAX, |end| end as usize);
+        if start == 1 && end == usize::MAX {
+            return Ok(content);
+        }
+        Ok(content
+            .lines()
+            .skip(start - 1)
+            .take(end.saturating_sub(start - 1))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+struct ListDir {
+    root: PathBuf,
+}
+
+impl Tool for ListDir {
+    fn name(&self) -> &str {
+        "list_dir"
+    }
+
+    fn description(&self) -> &str {
+        "List the entries of a directory in the current working directory. Directories end with `/`."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "Directory relative to the working directory, defaults to `.`" },
+            },
+        })
+    }
+
+    fn needs_confirmation(&self) -> bool {
+        false
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String> {
+        let path = resolve(&self.root, arguments["path"].as_str().unwrap_or("."))?;
+        let mut entries: Vec<String> = fs::read_dir(&path)
+            .with_context(|| format!("Failed to list {}", display(&self.root, &path)))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    format!("{}/", name)
+                } else {
+                    name
+                }
+            })
+            .collect();
+        entries.sort();
+        Ok(entries.join("\n"))
+    }
+}
+
+struct SearchText {
+    root: PathBuf,
+}
+
+impl Tool for SearchText {
+    fn name(&self) -> &str {
+        "search_text"
+    }
+
+    fn description(&self) -> &str {
+        "Search text files under a directory of the current working directory for lines containing a string. Hidden files, `target` and `node_modules` are skipped."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "pattern": { "type": "string", "description": "Text to look for (not a regex)" },
+                "path": { "type": "string", "description": "Directory or file to search, defaults to `.`" },
+                "ignore_case": { "type": "boolean" },
+            },
+            "required": ["pattern"],
+        })
+    }
+
+    fn needs_confirmation(&self) -> bool {
+        false
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String> {
+        let ignore_case = arguments["ignore_case"].as_bool().unwrap_or(false);
+        let normalize = |text: &str| if ignore_case { text.to_lowercase() } else { text.to_string() };
+        let pattern = normalize(string_arg(arguments, "pattern")?);
+        let start = resolve(&self.root, arguments["path"].as_str().unwrap_or("."))?;
+
+        let walker = WalkDir::new(&start).into_iter().filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            entry.depth() == 0 || !(name.starts_with('.') || name == "target" || name == "node_modules")
+        });
+        let mut matches = Vec::new();
+        for entry in walker.filter_map(|entry| entry.ok()) {
+            if !entry.file_type().is_file() || entry.metadata().map_or(true, |m| m.len() > MAX_FILE_SIZE) {
+                continue;
+            }
+            // 跳过二进制和非 UTF-8 文件
+            let Ok(content) = fs::read_to_string(entry.path()) else {
+                continue;
+            };
+            for (i, line) in content.lines().enumerate() {
+                if normalize(line).contains(&pattern) {
+                    matches.push(format!("{}:{}: {}", display(&self.root, entry.path()), i + 1, line.trim()));
+                    if matches.len() == MAX_MATCHES {
+                        matches.push(format!("[stopped after {} matches]", MAX_MATCHES));
+                        return Ok(matches.join("\n"));
+                    }
+                }
+            }
+        }
+        if matches.is_empty() {
+            return Ok("No matches".to_string());
+        }
+        Ok(matches.join("\n"))
+    }
+}
+
+struct WriteFile {
+    root: PathBuf,
+}
+
+impl Tool for WriteFile {
+    fn name(&self) -> &str {
+        "write_file"
+    }
+
+    fn description(&self) -> &str {
+        "Create or overwrite a file in the current working directory with the given content. The user confirms every write."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "Path relative to the working directory" },
+                "content": { "type": "string", "description": "The complete new content of the file" },
+            },
+            "required": ["path", "content"],
+        })
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String> {
+        let path = resolve(&self.root, string_arg(arguments, "path")?)?;
+        let content = string_arg(arguments, "content")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", display(&self.root, &path)))?;
+        Ok(format!("Wrote {} bytes to {}", content.len(), display(&self.root, &path)))
+    }
+}
+
+struct RunCommand {
+    root: PathBuf,
+    allowed: Vec<String>,
+}
+
+impl Tool for RunCommand {
+    fn name(&self) -> &str {
+        "run_command"
+    }
+
+    fn description(&self) -> &str {
+        "Run a program in the current working directory and return its output. The command is not passed to a shell, so pipes and redirections do not work. Only allowed programs can be run and the user confirms every run."
+    }
+
+    fn parameters(&self) -> Value {
+        let description = if self.allowed.is_empty() {
+            "Command line to run; no programs are currently allowed".to_string()
+        } else {
+            format!("Command line to run; allowed programs: {}", self.allowed.join(", "))
+        };
+        json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string", "description": description },
+            },
+            "required": ["command"],
+        })
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String> {
+        let command = string_arg(arguments, "command")?;
+        let words = shlex::split(command).with_context(|| format!("Cannot parse command `{}`", command))?;
+        let Some((program, args)) = words.split_first() else {
+            bail!("Empty command");
+        };
+        if !self.allowed.iter().any(|allowed| allowed == program) {
+            bail!("`{}` is not in allowed_commands", program);
+        }
+        let output = Command::new(program)
+            .args(args)
+            .current_dir(&self.root)
+            .output()
+            .with_context(|| format!("Failed to run `{}`", command))?;
+        Ok(command_output(&output))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn sandbox(name: &str) -> (PathBuf, PathBuf) {
+        let base = std::env::temp_dir().join(format!("chat-builtin-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let root = base.join("root");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(base.join("secret.txt"), "secret").unwrap();
+        (base.canonicalize().unwrap(), root.canonicalize().unwrap())
+    }
+
+    #[test]
+    fn resolves_paths_inside_root() {
+        let (base, root) = sandbox("inside");
+        assert_eq!(resolve(&root, "src/main.rs").unwrap(), root.join("src/main.rs"));
+        assert_eq!(resolve(&root, "src/../src/main.rs").unwrap(), root.join("src/main.rs"));
+        assert_eq!(resolve(&root, "src/new/file.rs").unwrap(), root.join("src/new/file.rs"));
+        assert_eq!(resolve(&root, ".").unwrap(), root);
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn rejects_parent_escapes() {
+        let (base, root) = sandbox("parent");
+        for path in ["..", "../secret.txt", "src/../../secret.txt", "missing/../../secret.txt", "/etc/passwd"] {
+            assert!(resolve(&root, path).is_err(), "{} was accepted", path);
+        }
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn rejects_symlink_escapes() {
+        let (base, root) = sandbox("symlink");
+        symlink(base.join("secret.txt"), root.join("link.txt")).unwrap();
+        symlink(&base, root.join("outside")).unwrap();
+        symlink(base.join("missing.txt"), root.join("dangling.txt")).unwrap();
+        symlink(root.join("src"), root.join("inside")).unwrap();
+
+        assert!(resolve(&root, "link.txt").is_err());
+        assert!(resolve(&root, "outside/secret.txt").is_err());
+        assert!(resolve(&root, "outside/new.txt").is_err());
+        assert!(resolve(&root, "dangling.txt").is_err());
+        assert_eq!(resolve(&root, "inside/main.rs").unwrap(), root.join("src/main.rs"));
+
+        let write = WriteFile { root: root.clone() };
+        assert!(write.call(&json!({ "path": "dangling.txt", "content": "x" })).is_err());
+        assert!(!base.join("missing.txt").exists());
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+mod builtin;
+mod shell;
+
+pub use builtin::register as register_builtin;
+pub use shell::ShellTool;
+
+use crate::bridge::ToolDefinition;
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::process::Output;
+
+/// 可以由模型调用的函数
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    /// 参数的 JSON Schema
+    fn parameters(&self) -> Value;
+    /// 执行前是否需要用户确认
+    fn needs_confirmation(&self) -> bool {
+        true
+    }
+    /// 执行并返回交给模型的文本结果
+    fn call(&self, arguments: &Value) -> Result<String>;
+}
+
+/// 工具执行前的确认：参数为工具名和解析后的参数，返回是否允许执行
+pub type Confirm = Box<dyn FnMut(&str, &Value) -> Result<bool> + Send>;
+
+/// 本次会话可用的工具
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, Box<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册一个工具，同名的会被替换
+    pub fn register(&mut self, tool: impl Tool + 'static) {
+        self.tools.insert(tool.name().to_string(), Box::new(tool));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Tool> {
+        self.tools.get(name).map(|tool| tool.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tools.keys().map(String::as_str)
+    }
+
+    /// 请求中 `tools` 字段的内容
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .values()
+            .map(|tool| ToolDefinition::function(tool.name(), tool.description(), tool.parameters()))
+            .collect()
+    }
+}
+
+/// 把子进程的输出整理成交给模型的文本：stdout、stderr，失败时附上退出码
+fn command_output(output: &Output) -> String {
+    let mut result = String::from_utf8_lossy(&output.stdout).into_owned();
+    result.push_str(&String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        result.push_str(&format!("\n(exit status {})", output.status.code().unwrap_or(-1)));
+    }
+    result
+}
@@ -0,0 +1,97 @@
+use super::{command_output, Tool};
+use crate::config::{ParameterType, ShellToolConfig};
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::process::Command;
+
+/// 由配置声明的 shell 命令工具
+pub struct ShellTool {
+    name: String,
+    config: ShellToolConfig,
+}
+
+impl ShellTool {
+    pub fn new(name: &str, config: ShellToolConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            config,
+        }
+    }
+
+    /// 把参数填进命令模板，每个值都按 shell 规则转义，模型无法借参数注入额外的命令
+    fn command_line(&self, arguments: &Value) -> Result<String> {
+        let mut command = self.config.command.clone();
+        for (name, parameter) in &self.config.parameters {
+            let value = match arguments.get(name) {
+                None | Some(Value::Null) if parameter.optional => String::new(),
+                None | Some(Value::Null) => bail!("Missing argument `{}`", name),
+                Some(Value::String(s)) => s.clone(),
+                Some(value @ (Value::Number(_) | Value::Bool(_))) => value.to_string(),
+                Some(value) => bail!("Argument `{}` must be a {:?}, got {}", name, parameter.kind, value),
+            };
+            command = command.replace(&format!("{{{}}}", name), &quote(&value));
+        }
+        Ok(command)
+    }
+}
+
+impl Tool for ShellTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.config.description
+    }
+
+    fn parameters(&self) -> Value {
+        let mut properties = serde_json::Map::new();
+        let mut required = Vec::new();
+        for (name, parameter) in &self.config.parameters {
+            let kind = match parameter.kind {
+                ParameterType::String => "string",
+                ParameterType::Integer => "integer",
+                ParameterType::Number => "number",
+                ParameterType::Boolean => "boolean",
+            };
+            let mut property = json!({ "type": kind });
+            if let Some(description) = &parameter.description {
+                property["description"] = description.clone().into();
+            }
+            properties.insert(name.clone(), property);
+            if !parameter.optional {
+                required.push(name.clone());
+            }
+        }
+        json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        })
+    }
+
+    fn needs_confirmation(&self) -> bool {
+        self.config.confirm
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String> {
+        let command = self.command_line(arguments)?;
+        let output = if cfg!(windows) {
+            Command::new("cmd").arg("/C").arg(&command).output()
+        } else {
+            Command::new("sh").arg("-c").arg(&command).output()
+        }
+        .with_context(|| format!("Failed to run `{}`", command))?;
+
+        Ok(command_output(&output))
+    }
+}
+
+/// 把一个值转义为单个 shell 参数
+fn quote(value: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
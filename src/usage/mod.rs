@@ -0,0 +1,283 @@
+use crate::bridge::Usage;
+use crate::config::{Budget, Config};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Local};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// 用量记录中的一条：一次请求的 token 数和费用
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub time: DateTime<Local>,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// 发出请求的系统用户，多人共用一个 key 时用来区分
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    #[serde(default)]
+    pub reasoning_tokens: u32,
+    /// 模型没有配置价格时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl Record {
+    pub fn new(config: &Config, model: &str, usage: &Usage) -> Self {
+        Self {
+            time: Local::now(),
+            model: model.to_string(),
+            profile: config.profile().map(str::to_string),
+            user: std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .ok(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            reasoning_tokens: usage.reasoning_tokens(),
+            cost: config
+                .pricing(model)
+                .map(|pricing| pricing.cost(usage)),
+        }
+    }
+}
+
+/// 追加一条记录
+///
+/// 每条记录一次 `write` 写入以追加模式打开的文件，多个进程同时写也不会互相覆盖。
+pub fn append(path: &Path, record: &Record) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create data directory")?;
+    }
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// 读取全部记录，无法解析的行会被跳过
+pub fn load(path: &Path) -> Result<Vec<Record>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// `chat usage --by` 的分组方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    Day,
+    Month,
+    Model,
+    Profile,
+    User,
+}
+
+impl GroupBy {
+    fn key(self, record: &Record) -> String {
+        match self {
+            GroupBy::Day => record.time.format("%Y-%m-%d").to_string(),
+            GroupBy::Month => record.time.format("%Y-%m").to_string(),
+            GroupBy::Model => record.model.clone(),
+            GroupBy::Profile => record.profile.clone().unwrap_or_else(|| "(none)".to_string()),
+            GroupBy::User => record.user.clone().unwrap_or_else(|| "(unknown)".to_string()),
+        }
+    }
+}
+
+/// 一组记录的合计
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Totals {
+    pub requests: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub reasoning_tokens: u64,
+    pub cost: f64,
+    /// 有多少次请求因为模型没有配置价格而没有计入费用
+    pub unpriced: usize,
+}
+
+impl Totals {
+    fn add(&mut self, record: &Record) {
+        self.requests += 1;
+        self.prompt_tokens += record.prompt_tokens as u64;
+        self.completion_tokens += record.completion_tokens as u64;
+        self.reasoning_tokens += record.reasoning_tokens as u64;
+        match record.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+    }
+}
+
+pub fn summarize<'a>(records: impl IntoIterator<Item = &'a Record>, by: GroupBy) -> BTreeMap<String, Totals> {
+    let mut groups: BTreeMap<String, Totals> = BTreeMap::new();
+    for record in records {
+        groups.entry(by.key(record)).or_default().add(record);
+    }
+    groups
+}
+
+pub fn total<'a>(records: impl IntoIterator<Item = &'a Record>) -> Totals {
+    let mut total = Totals::default();
+    for record in records {
+        total.add(record);
+    }
+    total
+}
+
+/// 今天和本月已花费的金额
+pub fn spent(records: &[Record]) -> (f64, f64) {
+    spent_at(records, Local::now())
+}
+
+/// 截至 `now` 所在的那一天和那个月已花费的金额
+fn spent_at(records: &[Record], now: DateTime<Local>) -> (f64, f64) {
+    records
+        .iter()
+        .filter(|record| record.time.year() == now.year() && record.time.month() == now.month())
+        .fold((0.0, 0.0), |(today, month), record| {
+            let cost = record.cost.unwrap_or(0.0);
+            let today = if record.time.day() == now.day() { today + cost } else { today };
+            (today, month + cost)
+        })
+}
+
+/// 发送前检查预算：超过上限时返回错误，超过提醒线时返回提醒
+pub fn check_budget(budget: &Budget, records: &[Record]) -> Result<Option<String>> {
+    check_budget_at(budget, records, Local::now())
+}
+
+fn check_budget_at(budget: &Budget, records: &[Record], now: DateTime<Local>) -> Result<Option<String>> {
+    let (today, month) = spent_at(records, now);
+    if let Some(limit) = budget.daily_limit.filter(|limit| today >= *limit) {
+        bail!("Daily budget reached: spent {:.4} of {:.4} today (raise budget.daily_limit to continue)", today, limit);
+    }
+    if let Some(limit) = budget.monthly_limit.filter(|limit| month >= *limit) {
+        bail!("Monthly budget reached: spent {:.4} of {:.4} this month (raise budget.monthly_limit to continue)", month, limit);
+    }
+    if let Some(warn) = budget.daily_warn.filter(|warn| today >= *warn) {
+        return Ok(Some(format!("Spent {:.4} today, over the daily warning of {:.4}", today, warn)));
+    }
+    if let Some(warn) = budget.monthly_warn.filter(|warn| month >= *warn) {
+        return Ok(Some(format!("Spent {:.4} this month, over the monthly warning of {:.4}", month, warn)));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn record(time: DateTime<Local>, model: &str, profile: Option<&str>, cost: Option<f64>) -> Record {
+        Record {
+            time,
+            model: model.to_string(),
+            profile: profile.map(str::to_string),
+            user: None,
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            reasoning_tokens: 0,
+            cost,
+        }
+    }
+
+    fn budget(daily_warn: Option<f64>, daily_limit: Option<f64>, monthly_warn: Option<f64>, monthly_limit: Option<f64>) -> Budget {
+        Budget {
+            daily_warn,
+            daily_limit,
+            monthly_warn,
+            monthly_limit,
+        }
+    }
+
+    #[test]
+    fn spent_uses_calendar_day_and_month() {
+        let records = [
+            record(at(2025, 4, 1, 12, 0), "a", None, Some(8.0)),
+            record(at(2026, 3, 1, 12, 0), "a", None, Some(4.0)),
+            record(at(2026, 3, 31, 23, 30), "a", None, Some(2.0)),
+            record(at(2026, 4, 1, 0, 10), "a", None, Some(0.5)),
+            record(at(2026, 4, 1, 9, 0), "a", None, None),
+        ];
+        let cases = [
+            (at(2026, 3, 31, 23, 59), (2.0, 6.0)),
+            (at(2026, 4, 1, 10, 0), (0.5, 0.5)),
+            (at(2026, 4, 2, 10, 0), (0.0, 0.5)),
+            (at(2026, 5, 1, 10, 0), (0.0, 0.0)),
+        ];
+        for (now, expected) in cases {
+            assert_eq!(spent_at(&records, now), expected, "at {}", now);
+        }
+    }
+
+    #[test]
+    fn budget_limits_and_warnings() {
+        let now = at(2026, 4, 15, 12, 0);
+        // 今天花了 1.0，本月共 3.0
+        let records = [
+            record(at(2026, 4, 1, 12, 0), "a", None, Some(2.0)),
+            record(at(2026, 4, 15, 9, 0), "a", None, Some(1.0)),
+            record(at(2026, 3, 31, 12, 0), "a", None, Some(100.0)),
+        ];
+        let cases: [(Budget, Result<Option<&str>, &str>); 9] = [
+            (budget(None, None, None, None), Ok(None)),
+            (budget(None, Some(1.0), None, None), Err("Daily budget reached")),
+            (budget(None, Some(1.5), None, None), Ok(None)),
+            (budget(None, None, None, Some(3.0)), Err("Monthly budget reached")),
+            (budget(None, None, None, Some(3.5)), Ok(None)),
+            (budget(Some(1.0), None, None, None), Ok(Some("over the daily warning"))),
+            (budget(Some(1.5), None, Some(3.0), None), Ok(Some("over the monthly warning"))),
+            (budget(Some(0.5), None, None, Some(2.0)), Err("Monthly budget reached")),
+            (budget(Some(1.5), Some(2.0), Some(4.0), Some(5.0)), Ok(None)),
+        ];
+        for (i, (budget, expected)) in cases.into_iter().enumerate() {
+            match (check_budget_at(&budget, &records, now), expected) {
+                (Ok(None), Ok(None)) => {}
+                (Ok(Some(message)), Ok(Some(expected))) => assert!(message.contains(expected), "case {}: {}", i, message),
+                (Err(e), Err(expected)) => assert!(e.to_string().contains(expected), "case {}: {}", i, e),
+                (result, expected) => panic!("case {}: got {:?}, expected {:?}", i, result, expected),
+            }
+        }
+    }
+
+    #[test]
+    fn summarize_groups_records() {
+        let records = [
+            record(at(2026, 3, 31, 12, 0), "gpt", Some("work"), Some(1.0)),
+            record(at(2026, 4, 1, 12, 0), "gpt", None, Some(0.5)),
+            record(at(2026, 4, 1, 13, 0), "qwen", Some("work"), None),
+        ];
+        let keys = |by| summarize(&records, by).into_keys().collect::<Vec<_>>();
+        assert_eq!(keys(GroupBy::Day), ["2026-03-31", "2026-04-01"]);
+        assert_eq!(keys(GroupBy::Month), ["2026-03", "2026-04"]);
+        assert_eq!(keys(GroupBy::Model), ["gpt", "qwen"]);
+        assert_eq!(keys(GroupBy::Profile), ["(none)", "work"]);
+        assert_eq!(keys(GroupBy::User), ["(unknown)"]);
+
+        let by_profile = summarize(&records, GroupBy::Profile);
+        let work = &by_profile["work"];
+        assert_eq!((work.requests, work.prompt_tokens, work.cost, work.unpriced), (2, 20, 1.0, 1));
+        let total = total(&records);
+        assert_eq!((total.requests, total.completion_tokens, total.cost, total.unpriced), (3, 15, 1.5, 1));
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use image::{DynamicImage, ImageFormat};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+/// 能读取的原始图片文件的最大大小
+pub const MAX_FILE_SIZE: u64 = 20 * 1024 * 1024;
+
+/// 发送前把长边缩小到这个像素数以内
+pub const MAX_DIMENSION: u32 = 2048;
+
+/// 编码后（base64 之前）允许发送的最大大小
+pub const MAX_ENCODED_SIZE: usize = 5 * 1024 * 1024;
+
+/// 读取一张图片，必要时缩小并重新编码，返回 `data:` URL
+///
+/// 尺寸合适的 PNG、JPEG 和 WebP 原样发送；其他格式或超出尺寸的图片缩小后
+/// 编码为 PNG（有透明通道时）或 JPEG。
+pub fn load(path: &Path) -> Result<String> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("Failed to read image {}", path.display()))?
+        .len();
+    if size > MAX_FILE_SIZE {
+        bail!("Image {} is larger than {} MB", path.display(), MAX_FILE_SIZE / 1024 / 1024);
+    }
+    let bytes = fs::read(path).with_context(|| format!("Failed to read image {}", path.display()))?;
+    let format = image::guess_format(&bytes)
+        .with_context(|| format!("{} is not a supported image (PNG, JPEG, GIF or WebP)", path.display()))?;
+    let image = image::load_from_memory_with_format(&bytes, format)
+        .with_context(|| format!("Failed to decode image {}", path.display()))?;
+
+    let fits = image.width() <= MAX_DIMENSION && image.height() <= MAX_DIMENSION;
+    let passthrough = matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP);
+    let (bytes, format) = if fits && passthrough && bytes.len() <= MAX_ENCODED_SIZE {
+        (bytes, format)
+    } else {
+        let image = if fits {
+            image
+        } else {
+            image.resize(MAX_DIMENSION, MAX_DIMENSION, image::imageops::FilterType::Triangle)
+        };
+        encode(&image)?
+    };
+    if bytes.len() > MAX_ENCODED_SIZE {
+        bail!(
+            "Image {} is still larger than {} MB after resizing",
+            path.display(),
+            MAX_ENCODED_SIZE / 1024 / 1024
+        );
+    }
+
+    Ok(format!(
+        "data:{};base64,{}",
+        format.to_mime_type(),
+        base64::engine::general_purpose::STANDARD.encode(&bytes)
+    ))
+}
+
+fn encode(image: &DynamicImage) -> Result<(Vec<u8>, ImageFormat)> {
+    let mut bytes = Vec::new();
+    let format = if image.color().has_alpha() {
+        image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+        ImageFormat::Png
+    } else {
+        // JPEG 不支持 16 位色深等格式，先统一转成 8 位 RGB
+        DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)?;
+        ImageFormat::Jpeg
+    };
+    Ok((bytes, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chat-vision-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn save(dir: &Path, name: &str, image: DynamicImage, format: ImageFormat) -> PathBuf {
+        let path = dir.join(name);
+        image.save_with_format(&path, format).unwrap();
+        path
+    }
+
+    /// 拆开 `data:` URL，返回 MIME 类型和解码后的图片内容
+    fn decode(url: &str) -> (String, Vec<u8>) {
+        let (mime, data) = url.strip_prefix("data:").unwrap().split_once(";base64,").unwrap();
+        (mime.to_string(), base64::engine::general_purpose::STANDARD.decode(data).unwrap())
+    }
+
+    #[test]
+    fn images_within_limits_are_sent_unchanged() {
+        let dir = temp_dir("fits");
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(MAX_DIMENSION, 16, Rgb([200, 10, 10])));
+        let path = save(&dir, "wide.png", image, ImageFormat::Png);
+
+        let (mime, bytes) = decode(&load(&path).unwrap());
+        assert_eq!(mime, "image/png");
+        assert_eq!(bytes, fs::read(&path).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn large_images_are_downscaled() {
+        let dir = temp_dir("large");
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(MAX_DIMENSION * 2, 64, Rgb([10, 200, 10])));
+        let path = save(&dir, "large.png", image, ImageFormat::Png);
+        let (mime, bytes) = decode(&load(&path).unwrap());
+        assert_eq!(mime, "image/jpeg");
+        let resized = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((resized.width(), resized.height()), (MAX_DIMENSION, 32));
+
+        // 有透明通道的图片编码为 PNG
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(64, MAX_DIMENSION + 1, Rgba([0, 0, 0, 128])));
+        let path = save(&dir, "tall.png", image, ImageFormat::Png);
+        let (mime, bytes) = decode(&load(&path).unwrap());
+        assert_eq!(mime, "image/png");
+        let resized = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(resized.height(), MAX_DIMENSION);
+        assert!(resized.color().has_alpha());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn other_formats_are_reencoded() {
+        let dir = temp_dir("gif");
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([0, 0, 255, 255])));
+        let path = save(&dir, "small.gif", image, ImageFormat::Gif);
+        let (mime, bytes) = decode(&load(&path).unwrap());
+        assert_eq!(mime, "image/png");
+        assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Png);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unsupported_files_are_rejected() {
+        let dir = temp_dir("unsupported");
+        let text = dir.join("notes.png");
+        fs::write(&text, "not an image").unwrap();
+        let error = load(&text).unwrap_err();
+        assert!(error.to_string().contains("is not a supported image"), "{:#}", error);
+
+        // 能识别但没有启用解码的格式（BMP）
+        let bmp = dir.join("image.bmp");
+        fs::write(&bmp, b"BM\0\0\0\0\0\0\0\0\0\0\0\0").unwrap();
+        let error = load(&bmp).unwrap_err();
+        assert!(error.to_string().contains("Failed to decode image"), "{:#}", error);
+
+        let huge = dir.join("huge.png");
+        fs::File::create(&huge).unwrap().set_len(MAX_FILE_SIZE + 1).unwrap();
+        let error = load(&huge).unwrap_err();
+        assert!(error.to_string().contains("larger than 20 MB"), "{:#}", error);
+
+        assert!(load(&dir.join("missing.png")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}